@@ -1,7 +1,7 @@
 use super::renderer::*;
 use nalgebra::*;
 use crate::consts::*;
-use rand::rngs::ThreadRng;
+use rand::RngCore;
 
 pub struct Camera {
     origin: super::renderer::Point,
@@ -20,6 +20,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         origin: super::renderer::Point,
         dir: Dir,
@@ -53,7 +54,7 @@ impl Camera {
         }
     }
 
-    pub fn generate_ray(&self, x: usize, y: usize, rng: &mut ThreadRng) -> Ray {
+    pub fn generate_ray(&self, x: usize, y: usize, rng: &mut dyn RngCore) -> Ray {
         use rand::Rng;
         let xdelta = rng.gen_range(-0.5f64, 0.5f64);
         let ydelta = rng.gen_range(-0.5f64, 0.5f64);
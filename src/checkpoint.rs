@@ -0,0 +1,239 @@
+use crate::renderer::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    Mismatch(String),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "{}", e),
+            CheckpointError::Format(e) => write!(f, "malformed checkpoint: {}", e),
+            CheckpointError::Mismatch(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<std::io::Error> for CheckpointError {
+    fn from(e: std::io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CheckpointError {
+    fn from(e: serde_json::Error) -> Self {
+        CheckpointError::Format(e)
+    }
+}
+
+/**
+ * Accumulated result after `iter` iterations. `data` is the sum of all iterations, indexed by [x][y]
+ */
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint<'a> {
+    pub iter: usize,
+    /// Absent in checkpoints saved before it was recorded
+    #[serde(default)]
+    pub integrator: Option<Integrator>,
+    /// Absent in merged checkpoints, and those saved before it was recorded
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub data: Cow<'a, [Vec<Color>]>,
+}
+
+impl<'a> Checkpoint<'a> {
+    pub fn load(path: &Path) -> Result<Checkpoint<'static>, CheckpointError> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let cp: Checkpoint<'static> = serde_json::from_reader(file)?;
+
+        cp.check_nonempty()?;
+        let height = cp.height();
+        if cp.data.iter().any(|col| col.len() != height) {
+            return Err(CheckpointError::Mismatch("columns have different heights".to_owned()));
+        }
+
+        Ok(cp)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub fn width(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn height(&self) -> usize {
+        self.data.first().map(Vec::len).unwrap_or(0)
+    }
+
+    /**
+     * Merges independently rendered checkpoints of the same size.
+     *
+     * Since the buffers are sums over iterations, adding them up weights each checkpoint by its iteration count.
+     * Checkpoints rendered with the same seed repeat the same iterations, and are rejected.
+     */
+    pub fn merge<I>(cps: I) -> Result<Checkpoint<'static>, CheckpointError>
+    where
+        I: IntoIterator<Item = Checkpoint<'a>>,
+    {
+        let mut iter = cps.into_iter();
+        let mut seeds = std::collections::HashSet::new();
+        let mut result = match iter.next() {
+            Some(cp) => {
+                cp.check_nonempty()?;
+                seeds.extend(cp.seed);
+                Checkpoint {
+                    iter: cp.iter,
+                    integrator: cp.integrator,
+                    seed: None,
+                    data: Cow::Owned(cp.data.into_owned()),
+                }
+            }
+            None => return Err(CheckpointError::Mismatch("nothing to merge".to_owned())),
+        };
+
+        for cp in iter {
+            cp.check_size(result.width(), result.height())?;
+            if let Some(seed) = cp.seed {
+                if !seeds.insert(seed) {
+                    return Err(CheckpointError::Mismatch(format!(
+                        "two checkpoints are rendered with the same seed {}",
+                        seed
+                    )));
+                }
+            }
+            if let Some(integrator) = result.integrator {
+                cp.check_integrator(integrator)?;
+            }
+            result.integrator = result.integrator.or(cp.integrator);
+            result.iter += cp.iter;
+            for (acc, col) in result.data.to_mut().iter_mut().zip(cp.data.iter()) {
+                for (acc, elem) in acc.iter_mut().zip(col.iter()) {
+                    *acc += elem;
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn check_nonempty(&self) -> Result<(), CheckpointError> {
+        if self.width() == 0 || self.height() == 0 {
+            return Err(CheckpointError::Mismatch("checkpoint has no pixels".to_owned()));
+        }
+        Ok(())
+    }
+
+    pub fn check_size(&self, width: usize, height: usize) -> Result<(), CheckpointError> {
+        if self.width() != width || self.height() != height {
+            return Err(CheckpointError::Mismatch(format!(
+                "checkpoint is {}x{}, expected {}x{}",
+                self.width(), self.height(), width, height
+            )));
+        }
+        Ok(())
+    }
+
+    /**
+     * Estimators of different integrators can't be summed up. Checkpoints without one pass
+     */
+    pub fn check_integrator(&self, integrator: Integrator) -> Result<(), CheckpointError> {
+        match self.integrator {
+            Some(saved) if saved != integrator => Err(CheckpointError::Mismatch(format!(
+                "checkpoint is rendered by the {} integrator, not {}",
+                saved, integrator
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(iter: usize, seed: Option<u64>, value: f64) -> Checkpoint<'static> {
+        Checkpoint {
+            iter,
+            integrator: Some(Integrator::Ppm),
+            seed,
+            data: Cow::Owned(vec![vec![Color::repeat(value); 2]; 3]),
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("meow_lens_{}_{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let path = temp_path("round_trip");
+        let saved = checkpoint(5, Some(42), 0.25f64);
+        saved.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.iter, 5);
+        assert_eq!(loaded.integrator, Some(Integrator::Ppm));
+        assert_eq!(loaded.seed, Some(42));
+        assert_eq!(loaded.data, saved.data);
+    }
+
+    #[test]
+    fn load_rejects_checkpoints_without_pixels() {
+        for (name, content) in &[
+            ("no_columns", r#"{"iter": 1, "data": []}"#),
+            ("no_rows", r#"{"iter": 1, "data": [[]]}"#),
+            ("ragged", r#"{"iter": 1, "data": [[[0, 0, 0]], []]}"#),
+        ] {
+            let path = temp_path(name);
+            std::fs::write(&path, content).unwrap();
+            let loaded = Checkpoint::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(matches!(loaded, Err(CheckpointError::Mismatch(_))), "{} is accepted", name);
+        }
+    }
+
+    #[test]
+    fn merge_sums_iterations_and_buffers() {
+        let merged = Checkpoint::merge(vec![checkpoint(2, Some(1), 1f64), checkpoint(3, Some(2), 0.5f64)]).unwrap();
+        assert_eq!(merged.iter, 5);
+        assert_eq!(merged.integrator, Some(Integrator::Ppm));
+        assert_eq!(merged.seed, None);
+        assert!(merged.data.iter().flatten().all(|c| *c == Color::repeat(1.5f64)));
+    }
+
+    #[test]
+    fn merge_rejects_repeated_seeds() {
+        let merged = Checkpoint::merge(vec![checkpoint(2, Some(1), 1f64), checkpoint(2, None, 1f64), checkpoint(2, Some(1), 1f64)]);
+        assert!(matches!(merged, Err(CheckpointError::Mismatch(msg)) if msg.contains("same seed 1")));
+
+        // Unknown seeds can't be compared
+        assert!(Checkpoint::merge(vec![checkpoint(2, None, 1f64), checkpoint(2, None, 1f64)]).is_ok());
+    }
+
+    #[test]
+    fn merge_rejects_mismatches() {
+        let empty = Checkpoint { data: Cow::Owned(Vec::new()), ..checkpoint(1, None, 0f64) };
+        assert!(Checkpoint::merge(vec![empty]).is_err());
+        assert!(Checkpoint::merge(Vec::new()).is_err());
+
+        let small = Checkpoint { data: Cow::Owned(vec![vec![Color::zeros(); 2]; 2]), ..checkpoint(1, Some(2), 0f64) };
+        assert!(Checkpoint::merge(vec![checkpoint(1, Some(1), 0f64), small]).is_err());
+
+        let path = Checkpoint { integrator: Some(Integrator::Path), ..checkpoint(1, Some(2), 0f64) };
+        assert!(Checkpoint::merge(vec![checkpoint(1, Some(1), 0f64), path]).is_err());
+    }
+}
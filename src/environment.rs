@@ -0,0 +1,324 @@
+use crate::light::*;
+use crate::object::BoundingBox;
+use crate::renderer::*;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::{BufRead, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum EnvironmentError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Format(String),
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentError::Io(e) => write!(f, "{}", e),
+            EnvironmentError::Image(e) => write!(f, "{}", e),
+            EnvironmentError::Format(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for EnvironmentError {}
+
+impl From<std::io::Error> for EnvironmentError {
+    fn from(e: std::io::Error) -> Self {
+        EnvironmentError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for EnvironmentError {
+    fn from(e: image::ImageError) -> Self {
+        EnvironmentError::Image(e)
+    }
+}
+
+fn luminance(c: &Color) -> f64 {
+    0.2126f64 * c[0] + 0.7152f64 * c[1] + 0.0722f64 * c[2]
+}
+
+/**
+ * Reads a PFM image, returning (width, height, pixels) with rows from top to bottom
+ */
+fn read_pfm(path: &Path) -> Result<(usize, usize, Vec<Color>), EnvironmentError> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+
+    // The header is three whitespace separated tokens after the magic, ending with a single whitespace
+    let mut header = Vec::new();
+    let mut token = String::new();
+    while header.len() < 4 {
+        let mut byte = [0u8];
+        file.read_exact(&mut byte)?;
+        if byte[0].is_ascii_whitespace() {
+            if !token.is_empty() {
+                header.push(std::mem::take(&mut token));
+            }
+        } else {
+            token.push(byte[0] as char);
+        }
+    }
+
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => return Err(EnvironmentError::Format(format!("not a PFM file, got magic `{}`", magic))),
+    };
+    let parse = |s: &str| s.parse::<f64>().map_err(|_| EnvironmentError::Format(format!("malformed PFM header `{}`", s)));
+    let width = parse(&header[1])? as usize;
+    let height = parse(&header[2])? as usize;
+    let little_endian = parse(&header[3])? < 0f64;
+
+    let mut data = vec![0u8; width * height * channels * 4];
+    file.read_exact(&mut data)?;
+    if !file.fill_buf()?.is_empty() {
+        log::warn!("Trailing data in {}", path.display());
+    }
+
+    let values: Vec<f64> = data.chunks_exact(4).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        (if little_endian { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }) as f64
+    }).collect();
+
+    let mut pixels = Vec::with_capacity(width * height);
+    // Scanlines are stored from bottom to top
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let p = &values[(y * width + x) * channels..];
+            pixels.push(if channels == 3 { Color::new(p[0], p[1], p[2]) } else { Color::new(p[0], p[0], p[0]) });
+        }
+    }
+
+    Ok((width, height, pixels))
+}
+
+fn read_hdr(path: &Path) -> Result<(usize, usize, Vec<Color>), EnvironmentError> {
+    let decoder = image::hdr::HdrDecoder::new(std::io::BufReader::new(std::fs::File::open(path)?))?;
+    let meta = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?.iter().map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect();
+    Ok((meta.width as usize, meta.height as usize, pixels))
+}
+
+/**
+ * Reads a linear .hdr or .pfm image, returning (width, height, pixels) with rows from top to bottom
+ */
+pub fn read_linear(path: &Path) -> Result<(usize, usize, Vec<Color>), EnvironmentError> {
+    let is_pfm = path.extension().map(|e| e == "pfm").unwrap_or(false);
+    let (width, height, pixels) = if is_pfm { read_pfm(path)? } else { read_hdr(path)? };
+    if width == 0 || height == 0 {
+        return Err(EnvironmentError::Format("empty image".to_owned()));
+    }
+    Ok((width, height, pixels))
+}
+
+/**
+ * Equirectangular environment map around the scene, with +Y at the top row.
+ *
+ * Values are what the camera sees, scaled like emitters: a white diffuse surface under a uniform environment
+ * looks as bright as the environment itself.
+ */
+pub struct Environment {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    rotation: f64,
+
+    // For importance sampling by luminance, compensated for the area of each row on the sphere
+    row_cdf: Vec<f64>,
+    col_cdfs: Vec<Vec<f64>>,
+    weights: Vec<f64>,
+    total_weight: f64,
+}
+
+/**
+ * Index of the first cdf entry above `target`
+ */
+fn search(cdf: &[f64], target: f64) -> usize {
+    cdf.partition_point(|c| *c <= target).min(cdf.len() - 1)
+}
+
+impl Environment {
+    /**
+     * Loads a .hdr or .pfm file. `rotation` turns the map around +Y, in radians
+     */
+    pub fn load(path: &Path, scale: f64, rotation: f64) -> Result<Self, EnvironmentError> {
+        let (width, height, pixels) = read_linear(path)?;
+        Ok(Self::new(width, height, pixels.into_iter().map(|p| p.map(|c| c.max(0f64)) * scale).collect(), rotation))
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, rotation: f64) -> Self {
+        let mut weights = Vec::with_capacity(width * height);
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut col_cdfs = Vec::with_capacity(height);
+        let mut total_weight = 0f64;
+
+        for y in 0..height {
+            let sin = ((y as f64 + 0.5f64) / height as f64 * PI).sin();
+            let mut acc = 0f64;
+            let mut col_cdf = Vec::with_capacity(width);
+            for x in 0..width {
+                let w = luminance(&pixels[y * width + x]).max(0f64) * sin;
+                weights.push(w);
+                acc += w;
+                col_cdf.push(acc);
+            }
+            total_weight += acc;
+            row_cdf.push(total_weight);
+            col_cdfs.push(col_cdf);
+        }
+
+        Self { width, height, pixels, rotation, row_cdf, col_cdfs, weights, total_weight }
+    }
+
+    /**
+     * Whether anything is emitted at all
+     */
+    pub fn is_black(&self) -> bool {
+        self.total_weight <= 0f64
+    }
+
+    /**
+     * Physical radiance integrated over the sphere, i.e. the flux through a unit area perpendicular to each direction
+     */
+    pub fn integral(&self) -> Color {
+        let mut total: Color = Default::default();
+        for y in 0..self.height {
+            let sin = ((y as f64 + 0.5f64) / self.height as f64 * PI).sin();
+            let solid_angle = 2f64 * PI * PI * sin / (self.width * self.height) as f64;
+            for x in 0..self.width {
+                total += self.pixels[y * self.width + x] * (solid_angle / PI);
+            }
+        }
+        total
+    }
+
+    /**
+     * Index of the pixel towards `dir`, along with the polar angle of `dir`
+     */
+    fn pixel(&self, dir: &Dir) -> (usize, f64) {
+        let dir = dir.normalize();
+        let theta = dir[1].clamp(-1f64, 1f64).acos();
+        let phi = (dir[2].atan2(dir[0]) + self.rotation).rem_euclid(2f64 * PI);
+
+        let x = ((phi / (2f64 * PI) * self.width as f64) as usize).min(self.width - 1);
+        let y = ((theta / PI * self.height as f64) as usize).min(self.height - 1);
+        (y * self.width + x, theta)
+    }
+
+    pub fn lookup(&self, dir: &Dir) -> Color {
+        self.pixels[self.pixel(dir).0]
+    }
+
+    /**
+     * Density per solid angle of `sample` choosing `dir`
+     */
+    pub fn pdf(&self, dir: &Dir) -> f64 {
+        if self.is_black() {
+            return 0f64;
+        }
+
+        let (idx, theta) = self.pixel(dir);
+        let pixel_prob = self.weights[idx] / self.total_weight;
+        pixel_prob * (self.width * self.height) as f64 / (2f64 * PI * PI * theta.sin().max(1e-8f64))
+    }
+
+    /**
+     * Samples a direction towards the environment by luminance, returning it with the physical radiance over the
+     * pdf (per solid angle) of choosing it.
+     */
+    pub fn sample(&self, rng: &mut dyn RngCore) -> (Dir, Color) {
+        let y = search(&self.row_cdf, rng.gen::<f64>() * self.total_weight);
+        let row = &self.col_cdfs[y];
+        let x = search(row, rng.gen::<f64>() * row[row.len() - 1]);
+
+        let u = (x as f64 + rng.gen::<f64>()) / self.width as f64;
+        let v = (y as f64 + rng.gen::<f64>()) / self.height as f64;
+        let theta = v * PI;
+        let phi = u * 2f64 * PI - self.rotation;
+        let dir = Dir::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+
+        // Uniform within the pixel, whose solid angle is (2pi / width) (pi / height) sin(theta)
+        let pixel_prob = self.weights[y * self.width + x] / self.total_weight;
+        let pdf = pixel_prob * (self.width * self.height) as f64 / (2f64 * PI * PI * theta.sin().max(1e-8f64));
+
+        // Values are scaled like emitters, i.e. pi times the radiance
+        (dir, self.pixels[y * self.width + x] / (PI * pdf))
+    }
+}
+
+/**
+ * Light from the environment, emitted from a disk just outside the bounding sphere of the scene
+ */
+pub struct EnvironmentLight {
+    env: Arc<Environment>,
+    center: Point,
+    radius: f64,
+}
+
+impl EnvironmentLight {
+    /**
+     * `bounds` should cover all objects
+     */
+    pub fn new(env: Arc<Environment>, bounds: &BoundingBox) -> Self {
+        Self {
+            env,
+            center: bounds.centroid(),
+            radius: bounds.extent().norm() / 2f64 + crate::consts::EPS,
+        }
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
+        let (towards, weight) = self.env.sample(rng);
+
+        let r = rng.gen::<f64>().sqrt() * self.radius;
+        let phi = rng.gen_range(0f64, 2f64 * PI);
+        let (tangent, bitangent) = tangents(&towards);
+        let origin = self.center + towards * (2f64 * self.radius) + tangent * (r * phi.cos()) + bitangent * (r * phi.sin());
+
+        let area = PI * self.radius * self.radius;
+        Photon {
+            ray: Ray::new(origin, -towards),
+            flux: weight * (area / total_photon_number as f64),
+        }
+    }
+
+    fn sample_direct(&self, at: &Point, rng: &mut dyn RngCore) -> Option<DirectSample> {
+        let (towards, weight) = self.env.sample(rng);
+
+        // Up to the plane photons are emitted from
+        let dist = 2f64 * self.radius - (at - self.center).dot(&towards);
+        if dist <= 0f64 {
+            return None;
+        }
+
+        Some(DirectSample {
+            dir: towards,
+            dist,
+            irradiance: weight,
+            pdf: self.env.pdf(&towards),
+        })
+    }
+
+    fn direct_pdf(&self, _at: &Point, dir: &Dir, dist: f64) -> f64 {
+        // Reached by rays leaving the scene
+        if dist.is_finite() {
+            return 0f64;
+        }
+        self.env.pdf(dir)
+    }
+
+    fn power(&self) -> Color {
+        self.env.integral() * (PI * self.radius * self.radius)
+    }
+
+    fn kind(&self) -> &'static str {
+        "environment"
+    }
+}
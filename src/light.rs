@@ -1,9 +1,10 @@
+use super::object::geometry::Surface;
+use super::object::BoundingBox;
 use super::renderer::*;
-use rand::rngs::ThreadRng;
-use nalgebra::Vector3;
+use super::spectrum::Spectrum;
+use rand::RngCore;
 use rand::Rng;
 use nalgebra::Rotation3;
-use crate::consts::*;
 
 #[derive(Clone, Debug)]
 pub struct Photon {
@@ -11,8 +12,156 @@ pub struct Photon {
     pub flux: Color,
 }
 
+/**
+ * Light arriving directly at a point, ignoring occlusion
+ */
+#[derive(Clone, Debug)]
+pub struct DirectSample {
+    /// From the receiving point towards the light
+    pub dir: Dir,
+    /// Distance to the light, for the shadow ray
+    pub dist: f64,
+    /// Irradiance on a surface perpendicular to `dir`
+    pub irradiance: Color,
+    /// Density per solid angle of choosing `dir`, infinite for lights from a single point or direction
+    pub pdf: f64,
+}
+
 pub trait Light: Send + Sync {
-    fn emit_photon(&self, total_photon_number: usize, rng: &mut ThreadRng) -> Photon;
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon;
+
+    /**
+     * Samples the light arriving at `at`, or None if the light doesn't reach `at` at all
+     */
+    fn sample_direct(&self, at: &Point, rng: &mut dyn RngCore) -> Option<DirectSample>;
+
+    /**
+     * Density per solid angle of `sample_direct` at `at` choosing `dir`, where a ray from `at` along `dir` first hits
+     * something at `dist`, or infinity if nothing. Zero for lights that rays can't hit
+     */
+    fn direct_pdf(&self, _at: &Point, _dir: &Dir, _dist: f64) -> f64 {
+        0f64
+    }
+
+    /**
+     * Total flux emitted, for choosing lights in the photon pass
+     */
+    fn power(&self) -> Color;
+
+    /**
+     * Short name of the light type, for logging
+     */
+    fn kind(&self) -> &'static str;
+
+    /**
+     * Spectral power distribution in the spectral mode. Without one, the RGB color is upsampled
+     */
+    fn spectrum(&self) -> Option<&Spectrum> {
+        None
+    }
+}
+
+/**
+ * Light following `spectrum` in the spectral mode. Its color should be the RGB of the spectrum, i.e. `Spectrum::rgb`
+ * scaled to the wanted luminance
+ */
+pub struct SpectralLight {
+    light: Box<dyn Light>,
+    spectrum: Spectrum,
+}
+
+impl SpectralLight {
+    pub fn new(light: Box<dyn Light>, spectrum: Spectrum) -> Self {
+        Self { light, spectrum }
+    }
+}
+
+impl Light for SpectralLight {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
+        self.light.emit_photon(total_photon_number, rng)
+    }
+
+    fn sample_direct(&self, at: &Point, rng: &mut dyn RngCore) -> Option<DirectSample> {
+        self.light.sample_direct(at, rng)
+    }
+
+    fn direct_pdf(&self, at: &Point, dir: &Dir, dist: f64) -> f64 {
+        self.light.direct_pdf(at, dir, dist)
+    }
+
+    fn power(&self) -> Color {
+        self.light.power()
+    }
+
+    fn kind(&self) -> &'static str {
+        self.light.kind()
+    }
+
+    fn spectrum(&self) -> Option<&Spectrum> {
+        Some(&self.spectrum)
+    }
+}
+
+/**
+ * All lights of a scene. Photons are emitted from lights chosen proportionally to their power,
+ * so that every photon carries about the same flux.
+ */
+pub struct LightSet {
+    lights: Vec<Box<dyn Light>>,
+    // Cumulative selection weights
+    cdf: Vec<f64>,
+}
+
+impl LightSet {
+    pub fn new(lights: Vec<Box<dyn Light>>) -> Self {
+        let mut weights: Vec<f64> = lights.iter().map(|l| (l.power().sum() / 3f64).max(0f64)).collect();
+
+        // Falls back to uniform if the power tells nothing, e.g. all lights are black
+        let total: f64 = weights.iter().sum();
+        if !(total > 0f64 && total.is_finite()) {
+            weights = vec![1f64; lights.len()];
+        }
+
+        let cdf = weights.iter().scan(0f64, |acc, w| {
+            *acc += w;
+            Some(*acc)
+        }).collect();
+
+        Self { lights, cdf }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> &dyn Light {
+        self.lights[idx].as_ref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Light> {
+        self.lights.iter().map(|l| l.as_ref())
+    }
+
+    /**
+     * Probability of choosing the light at `idx`
+     */
+    pub fn probability(&self, idx: usize) -> f64 {
+        let prev = if idx == 0 { 0f64 } else { self.cdf[idx - 1] };
+        (self.cdf[idx] - prev) / self.cdf[self.cdf.len() - 1]
+    }
+
+    /**
+     * Chooses a light by power, returning its index and the probability of choosing it
+     */
+    pub fn choose(&self, rng: &mut dyn RngCore) -> (usize, f64) {
+        let target = rng.gen::<f64>() * self.cdf[self.cdf.len() - 1];
+        let idx = self.cdf.partition_point(|c| *c <= target).min(self.lights.len() - 1);
+        (idx, self.probability(idx))
+    }
 }
 
 pub struct SemisphereLight {
@@ -29,7 +178,7 @@ impl SemisphereLight {
 }
 
 impl Light for SemisphereLight {
-    fn emit_photon(&self, total_photon_number: usize, rng: &mut ThreadRng) -> Photon {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
         let flux = self.color.clone_owned() * (self.total_flux / total_photon_number as f64);
         let mut dir: Dir = rng.gen();
         dir *= 2f64;
@@ -46,6 +195,31 @@ impl Light for SemisphereLight {
             flux,
         }
     }
+
+    fn sample_direct(&self, at: &Point, _rng: &mut dyn RngCore) -> Option<DirectSample> {
+        let diff = self.at - at;
+        let dist = diff.norm();
+        if dist < crate::consts::EPS || diff.dot(&self.towards) > 0f64 {
+            return None;
+        }
+
+        // Uniform over the hemisphere, so the intensity is flux / 2pi
+        let intensity = self.color * (self.total_flux / (2f64 * std::f64::consts::PI));
+        Some(DirectSample {
+            dir: diff / dist,
+            dist,
+            irradiance: intensity / (dist * dist),
+            pdf: f64::INFINITY,
+        })
+    }
+
+    fn power(&self) -> Color {
+        self.color * self.total_flux
+    }
+
+    fn kind(&self) -> &'static str {
+        "semisphere"
+    }
 }
 
 pub struct BeamLight {
@@ -68,7 +242,7 @@ impl BeamLight {
 }
 
 impl Light for BeamLight {
-    fn emit_photon(&self, total_photon_number: usize, rng: &mut ThreadRng) -> Photon {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
         let flux = self.color.clone_owned() * (self.total_flux / total_photon_number as f64);
 
         let radius = rng.gen::<f64>().sqrt() * self.radius;
@@ -80,4 +254,360 @@ impl Light for BeamLight {
             flux,
         }
     }
+
+    fn sample_direct(&self, at: &Point, _rng: &mut dyn RngCore) -> Option<DirectSample> {
+        let diff = at - self.origin;
+        let dist = diff.dot(&self.dir);
+        if dist <= 0f64 || (diff - self.dir * dist).norm() > self.radius {
+            return None;
+        }
+
+        let area = std::f64::consts::PI * self.radius * self.radius;
+        Some(DirectSample {
+            dir: -self.dir,
+            dist,
+            irradiance: self.color * (self.total_flux / area),
+            pdf: f64::INFINITY,
+        })
+    }
+
+    fn power(&self) -> Color {
+        self.color * self.total_flux
+    }
+
+    fn kind(&self) -> &'static str {
+        "beam"
+    }
+}
+
+fn uniform_sphere(rng: &mut dyn RngCore) -> Dir {
+    let [x, y, z]: [f64; 3] = rng.sample(rand_distr::UnitSphere);
+    Dir::new(x, y, z)
+}
+
+/**
+ * Orthonormal vectors perpendicular to `normal`
+ */
+pub fn tangents(normal: &Dir) -> (Dir, Dir) {
+    let helper = if normal[0].abs() < 0.9f64 { Dir::new(1f64, 0f64, 0f64) } else { Dir::new(0f64, 1f64, 0f64) };
+    let tangent = normal.cross(&helper).normalize();
+    (tangent, normal.cross(&tangent))
+}
+
+/**
+ * Isotropic point light
+ */
+pub struct PointLight {
+    at: Point,
+    color: Color,
+    total_flux: f64,
+}
+
+impl PointLight {
+    pub fn new(at: Point, color: Color, total_flux: f64) -> Self {
+        Self { at, color, total_flux }
+    }
+}
+
+impl Light for PointLight {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
+        Photon {
+            ray: Ray::new(self.at, uniform_sphere(rng)),
+            flux: self.color * (self.total_flux / total_photon_number as f64),
+        }
+    }
+
+    fn sample_direct(&self, at: &Point, _rng: &mut dyn RngCore) -> Option<DirectSample> {
+        let diff = self.at - at;
+        let dist = diff.norm();
+        if dist < crate::consts::EPS {
+            return None;
+        }
+
+        let intensity = self.color * (self.total_flux / (4f64 * std::f64::consts::PI));
+        Some(DirectSample {
+            dir: diff / dist,
+            dist,
+            irradiance: intensity / (dist * dist),
+            pdf: f64::INFINITY,
+        })
+    }
+
+    fn power(&self) -> Color {
+        self.color * self.total_flux
+    }
+
+    fn kind(&self) -> &'static str {
+        "point"
+    }
+}
+
+/**
+ * Solid angle of a spot light weighted by its falloff. The falloff is a smoothstep over cos,
+ * which integrates to half of the transition band
+ */
+fn spot_solid_angle(cos_inner: f64, cos_outer: f64) -> f64 {
+    2f64 * std::f64::consts::PI * ((1f64 - cos_inner) + (cos_inner - cos_outer) / 2f64)
+}
+
+/**
+ * Point light emitting within a cone. The intensity is full within the inner angle,
+ * and falls off smoothly to zero at the outer angle.
+ */
+pub struct SpotLight {
+    at: Point,
+    dir: Dir,
+    cos_inner: f64,
+    cos_outer: f64,
+    color: Color,
+    /// Intensity along the axis, so that the total flux is as given
+    peak: f64,
+}
+
+impl SpotLight {
+    /**
+     * Angles are measured from the axis, in radians
+     */
+    pub fn new(at: Point, dir: Dir, inner: f64, outer: f64, color: Color, total_flux: f64) -> Self {
+        let (cos_inner, cos_outer) = (inner.cos(), outer.cos());
+        Self {
+            at,
+            dir: dir.normalize(),
+            cos_inner,
+            cos_outer,
+            color,
+            peak: total_flux / spot_solid_angle(cos_inner, cos_outer),
+        }
+    }
+
+    fn falloff(&self, cos: f64) -> f64 {
+        if cos >= self.cos_inner {
+            return 1f64;
+        }
+        if cos <= self.cos_outer {
+            return 0f64;
+        }
+        let t = (cos - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3f64 - 2f64 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
+        // Uniform within the outer cone, weighted by the falloff
+        let cos = rng.gen_range(self.cos_outer, 1f64);
+        let sin = (1f64 - cos * cos).max(0f64).sqrt();
+        let phi = rng.gen_range(0f64, std::f64::consts::PI * 2f64);
+        let (tangent, bitangent) = tangents(&self.dir);
+        let dir = self.dir * cos + tangent * (sin * phi.cos()) + bitangent * (sin * phi.sin());
+
+        let cone = 2f64 * std::f64::consts::PI * (1f64 - self.cos_outer);
+        Photon {
+            ray: Ray::new(self.at, dir),
+            flux: self.color * (self.peak * self.falloff(cos) * cone / total_photon_number as f64),
+        }
+    }
+
+    fn sample_direct(&self, at: &Point, _rng: &mut dyn RngCore) -> Option<DirectSample> {
+        let diff = self.at - at;
+        let dist = diff.norm();
+        if dist < crate::consts::EPS {
+            return None;
+        }
+
+        let dir = diff / dist;
+        let falloff = self.falloff(-dir.dot(&self.dir));
+        if falloff <= 0f64 {
+            return None;
+        }
+
+        Some(DirectSample {
+            dir,
+            dist,
+            irradiance: self.color * (self.peak * falloff / (dist * dist)),
+            pdf: f64::INFINITY,
+        })
+    }
+
+    fn power(&self) -> Color {
+        self.color * (self.peak * spot_solid_angle(self.cos_inner, self.cos_outer))
+    }
+
+    fn kind(&self) -> &'static str {
+        "spot"
+    }
+}
+
+/**
+ * Parallel light from infinitely far away, e.g. the sun.
+ *
+ * Photons are emitted from a disk just outside the given bounds, which should cover the whole scene.
+ */
+pub struct DirectionalLight {
+    dir: Dir,
+    color: Color,
+    irradiance: f64,
+
+    origin: Point,
+    radius: f64,
+}
+
+impl DirectionalLight {
+    /**
+     * `irradiance` is on surfaces perpendicular to `dir`
+     */
+    pub fn new(dir: Dir, color: Color, irradiance: f64, bounds: &BoundingBox) -> Self {
+        let dir = dir.normalize();
+        let radius = bounds.extent().norm() / 2f64 + crate::consts::EPS;
+        Self {
+            dir,
+            color,
+            irradiance,
+            origin: bounds.centroid() - dir * (radius * 2f64),
+            radius,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
+        let area = std::f64::consts::PI * self.radius * self.radius;
+
+        let r = rng.gen::<f64>().sqrt() * self.radius;
+        let phi = rng.gen_range(0f64, std::f64::consts::PI * 2f64);
+        let (tangent, bitangent) = tangents(&self.dir);
+
+        Photon {
+            ray: Ray::new(self.origin + tangent * (r * phi.cos()) + bitangent * (r * phi.sin()), self.dir),
+            flux: self.color * (self.irradiance * area / total_photon_number as f64),
+        }
+    }
+
+    fn sample_direct(&self, at: &Point, _rng: &mut dyn RngCore) -> Option<DirectSample> {
+        let dist = (at - self.origin).dot(&self.dir);
+        if dist <= 0f64 {
+            return None;
+        }
+
+        Some(DirectSample {
+            dir: -self.dir,
+            dist,
+            irradiance: self.color * self.irradiance,
+            pdf: f64::INFINITY,
+        })
+    }
+
+    fn power(&self) -> Color {
+        self.color * (self.irradiance * std::f64::consts::PI * self.radius * self.radius)
+    }
+
+    fn kind(&self) -> &'static str {
+        "directional"
+    }
+}
+
+/**
+ * Cosine weighted direction on the hemisphere around `normal`
+ */
+pub fn cosine_weighted(normal: &Dir, rng: &mut dyn RngCore) -> Dir {
+    let (tangent, bitangent) = tangents(normal);
+
+    let r = rng.gen::<f64>().sqrt();
+    let phi = rng.gen_range(0f64, std::f64::consts::PI * 2f64);
+    let z = (1f64 - r * r).max(0f64).sqrt();
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * z).normalize()
+}
+
+/**
+ * Lambertian emitter over the surfaces of an object, e.g. all triangles of a mesh.
+ * Emits from the side opposite to the normals, matching `Material::emission`.
+ */
+pub struct AreaLight {
+    surfaces: Vec<Box<dyn Surface>>,
+    // Cumulative areas, for choosing surfaces proportionally to their area
+    cdf: Vec<f64>,
+    area: f64,
+    emission: Color,
+}
+
+impl AreaLight {
+    pub fn new(surfaces: Vec<Box<dyn Surface>>, emission: Color) -> Self {
+        let cdf: Vec<f64> = surfaces.iter().scan(0f64, |acc, s| {
+            *acc += s.area();
+            Some(*acc)
+        }).collect();
+        let area = cdf.last().cloned().unwrap_or(0f64);
+
+        Self { surfaces, cdf, area, emission }
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> (Point, Dir) {
+        let target = rng.gen::<f64>() * self.area;
+        let idx = self.cdf.partition_point(|c| *c <= target).min(self.surfaces.len() - 1);
+        self.surfaces[idx].sample(rng)
+    }
+}
+
+impl Light for AreaLight {
+    fn emit_photon(&self, total_photon_number: usize, rng: &mut dyn RngCore) -> Photon {
+        let flux = self.emission * (self.area / total_photon_number as f64);
+        let (at, norm) = self.sample_surface(rng);
+
+        Photon {
+            ray: Ray::new(at, cosine_weighted(&-norm, rng)),
+            flux,
+        }
+    }
+
+    fn sample_direct(&self, at: &Point, rng: &mut dyn RngCore) -> Option<DirectSample> {
+        let (on, norm) = self.sample_surface(rng);
+        let diff = on - at;
+        let dist = diff.norm();
+        if dist < crate::consts::EPS {
+            return None;
+        }
+
+        // Emitting towards -norm, so light travelling along -dir leaves the surface with this cosine
+        let dir = diff / dist;
+        let cos = dir.dot(&norm);
+        if cos <= 0f64 {
+            return None;
+        }
+
+        // Radiance of a lambertian emitter is exitance / pi, and each sample stands for the whole area
+        Some(DirectSample {
+            dir,
+            dist,
+            irradiance: self.emission * (cos * self.area / (std::f64::consts::PI * dist * dist)),
+            pdf: dist * dist / (cos * self.area),
+        })
+    }
+
+    fn direct_pdf(&self, at: &Point, dir: &Dir, dist: f64) -> f64 {
+        let ray = Ray::new(*at, *dir);
+        let hit = self.surfaces
+            .iter()
+            .filter_map(|s| s.intersect(&ray, Some(dist + crate::consts::EPS)))
+            .min_by(|a, b| a.dist.total_cmp(&b.dist));
+
+        // Only if the surface hit is one of this light
+        match hit {
+            Some(hit) if hit.dist >= dist - crate::consts::EPS => {
+                let cos = dir.dot(&hit.geo_norm).abs() / (dir.norm() * hit.geo_norm.norm());
+                if cos <= 0f64 {
+                    return 0f64;
+                }
+                hit.dist * hit.dist / (cos * self.area)
+            }
+            _ => 0f64,
+        }
+    }
+
+    fn power(&self) -> Color {
+        self.emission * self.area
+    }
+
+    fn kind(&self) -> &'static str {
+        "area"
+    }
 }
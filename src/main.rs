@@ -7,9 +7,18 @@ mod camera;
 mod light;
 mod consts;
 mod scene;
+mod checkpoint;
+mod output;
+mod tonemap;
+mod environment;
+mod texture;
+mod spectrum;
+mod medium;
+mod phase;
 
 use structopt::StructOpt;
-use log::info;
+use log::{info, warn};
+use std::path::{Path, PathBuf};
 
 #[derive(StructOpt, Debug)]
 pub struct Args {
@@ -41,7 +50,8 @@ pub struct Args {
     #[structopt(short, long, default_value="1")]
     supersampling: usize,
 
-    #[structopt(short, long, default_value="16")]
+    /// Iterations between checkpoints, at least 1
+    #[structopt(short, long, default_value="16", parse(try_from_str = parse_positive))]
     checkpoint: usize,
 
     #[structopt(short, long, default_value="2")]
@@ -55,13 +65,191 @@ pub struct Args {
 
     #[structopt(short, long, default_value="2")]
     volumetric_radius_ratio: f64,
+
+    /// Load the scene from a JSON or TOML description file
+    #[structopt(long, parse(from_os_str), conflicts_with = "builtin")]
+    scene: Option<PathBuf>,
+
+    /// Render a built-in scene, see --list-scenes
+    #[structopt(long, default_value = "volumetric")]
+    builtin: String,
+
+    /// List built-in scenes and exit
+    #[structopt(long)]
+    list_scenes: bool,
+
+    /// Rendering algorithm: ppm (probabilistic PPM), sppm (stochastic PPM with per-pixel radius) or path (path tracing)
+    #[structopt(long, default_value = "ppm")]
+    integrator: renderer::Integrator,
+
+    /// Trace a single sampled wavelength per eye path and photon instead of RGB, for dispersion and light spectra
+    #[structopt(long)]
+    spectral: bool,
+
+    /// Seed for random numbers. Renders with the same seed and thread count are identical
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Continue rendering from a saved checkpoint, up to --iter iterations in total
+    #[structopt(long, parse(from_os_str))]
+    resume: Option<PathBuf>,
+
+    /// Image formats to write, comma separated: png, pfm, hdr, exr
+    #[structopt(long, default_value = "png", use_delimiter = true, global = true)]
+    output_format: Vec<output::OutputFormat>,
+
+    /// Tone mapping operator for PNG output: clamp, reinhard, reinhard-extended, aces
+    #[structopt(long, default_value = "clamp", global = true)]
+    tonemap: tonemap::Operator,
+
+    /// Exposure adjustment before tone mapping, in EV
+    #[structopt(long, default_value = "0", allow_hyphen_values = true, global = true)]
+    exposure: f64,
+
+    /// Luminance mapped to white by reinhard-extended
+    #[structopt(long, default_value = "4", global = true)]
+    white_point: f64,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Merge checkpoints rendered independently, e.g. on different machines with different seeds
+    Merge {
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Writes <output>.json and <output>.<format>
+        #[structopt(short, long, default_value = "merged")]
+        output: String,
+    },
+
+    /// Write images from a checkpoint, e.g. with different tone mapping
+    Tonemap {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Writes <output>.<format>
+        #[structopt(short, long, default_value = "tonemapped")]
+        output: String,
+    },
+}
+
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl Args {
+    pub fn tone_mapping(&self) -> tonemap::ToneMapping {
+        tonemap::ToneMapping::new(self.tonemap, self.exposure, self.white_point)
+    }
+}
+
+fn merge(inputs: &[PathBuf], output: &str, args: &Args) -> Result<(), String> {
+    let mut cps = Vec::with_capacity(inputs.len());
+    for path in inputs {
+        let cp = checkpoint::Checkpoint::load(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        info!("Loaded {}: {} iterations", path.display(), cp.iter);
+        cps.push(cp);
+    }
+
+    let merged = checkpoint::Checkpoint::merge(cps).map_err(|e| e.to_string())?;
+    info!("Merged {} iterations", merged.iter);
+
+    merged.save(format!("{}.json", output).as_ref()).map_err(|e| e.to_string())?;
+    output::write_all(&merged.data, merged.iter, &args.output_format, &args.tone_mapping(), output)
+}
+
+fn tonemap(input: &Path, output: &str, args: &Args) -> Result<(), String> {
+    let cp = checkpoint::Checkpoint::load(input).map_err(|e| format!("{}: {}", input.display(), e))?;
+    output::write_all(&cp.data, cp.iter, &args.output_format, &args.tone_mapping(), output)
 }
 
 #[paw::main]
-fn main(args: Args) {
+fn main(mut args: Args) {
     env_logger::init();
+
+    if args.list_scenes {
+        for (name, desc, _) in scene::BUILTIN_SCENES {
+            println!("{:<12} {}", name, desc);
+        }
+        return;
+    }
+
+    match &args.cmd {
+        Some(Command::Merge { inputs, output }) => {
+            if let Err(e) = merge(inputs, output, &args) {
+                eprintln!("Failed to merge checkpoints: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Tonemap { input, output }) => {
+            if let Err(e) = tonemap(input, output, &args) {
+                eprintln!("Failed to write images: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+
     info!("Starting with parameters: {:?}", args);
 
-    let scene = scene::Scene::volumetric_scene(&args);
-    renderer::render(args, scene);
+    if args.resume.is_some() && args.integrator == renderer::Integrator::Sppm {
+        eprintln!("Resuming is not supported by the sppm integrator, as pixel statistics are not saved in checkpoints");
+        std::process::exit(1);
+    }
+
+    let resume = args.resume.as_ref().map(|path| {
+        let cp = checkpoint::Checkpoint::load(path).and_then(|cp| {
+            cp.check_size(args.width, args.height)?;
+            cp.check_integrator(args.integrator)?;
+            Ok(cp)
+        });
+
+        match cp {
+            Ok(cp) => cp,
+            Err(e) => {
+                eprintln!("Failed to resume from {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    if let Some(cp) = &resume {
+        if cp.integrator.is_none() {
+            warn!("The checkpoint doesn't record its integrator, assuming {}", args.integrator);
+        }
+        if cp.iter >= args.iter {
+            eprintln!("The checkpoint already has {} iterations, nothing left to render for --iter {}", cp.iter, args.iter);
+            return;
+        }
+        // Continues the same random sequence as an uninterrupted render
+        if args.seed.is_none() {
+            args.seed = cp.seed;
+        }
+    }
+
+    let scene = if let Some(path) = &args.scene {
+        match scene::Scene::from_file(path, &args) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("Failed to load scene {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(builder) = scene::builtin(&args.builtin) {
+        builder(&args)
+    } else {
+        eprintln!("Unknown built-in scene: {}, see --list-scenes", args.builtin);
+        std::process::exit(1);
+    };
+    renderer::render(args, scene, resume);
 }
@@ -0,0 +1,79 @@
+use super::microfacet::{fresnel_conductor, reflect, Ggx};
+use crate::renderer::*;
+use crate::spectrum::Wavelength;
+use rand::RngCore;
+
+/**
+ * Complex indices of refraction (eta, k) of common metals at roughly 650, 550 and 450 nm
+ */
+const METALS: &[(&str, [f64; 3], [f64; 3])] = &[
+    ("gold", [0.143, 0.374, 1.442], [3.983, 2.386, 1.603]),
+    ("silver", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+    ("copper", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+    ("aluminium", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("iron", [2.912, 2.950, 2.585], [3.089, 2.932, 2.767]),
+    ("chromium", [3.105, 3.173, 2.323], [3.329, 3.332, 3.135]),
+];
+
+/**
+ * Complex IOR of a metal by name, see `METALS`
+ */
+pub fn metal_ior(name: &str) -> Option<(Color, Color)> {
+    METALS.iter().find(|(n, _, _)| *n == name).map(|(_, eta, k)| (Color::from(*eta), Color::from(*k)))
+}
+
+pub fn metal_names() -> impl Iterator<Item = &'static str> {
+    METALS.iter().map(|(n, _, _)| *n)
+}
+
+/**
+ * Rough metal with GGX microfacets, reflecting on both sides
+ */
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: Ggx,
+}
+
+impl Conductor {
+    /**
+     * `eta` + i `k` is the complex IOR relative to the surrounding medium
+     */
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self { eta, k, distribution: Ggx::new(roughness) }
+    }
+
+    fn reflect(&self, at: &Point, inc: &Dir, norm: &Dir, rng: &mut dyn RngCore) -> (Ray, Color) {
+        let wi = -inc;
+        let normal = if wi.dot(norm) < 0f64 { -norm } else { *norm };
+
+        let m = self.distribution.sample_visible(&wi, &normal, rng);
+        let wo = reflect(&wi, &m);
+        if wo.dot(&normal) <= 0f64 {
+            return (Ray::new(*at, wo), Color::zeros());
+        }
+
+        let throughput = fresnel_conductor(wi.dot(&m), &self.eta, &self.k) * self.distribution.shadowing_weight(&wi, &wo, &normal);
+        (Ray::new(*at, wo), throughput)
+    }
+}
+
+impl super::Material for Conductor {
+    fn is_lambertian(&self) -> bool {
+        false
+    }
+    fn get_lambertian_ratio(&self, _uv: &UV) -> Color {
+        Color::zeros()
+    }
+
+    fn get_vision_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, _uv: &UV, _wavelength: Wavelength, rng: &mut dyn RngCore) -> super::Reflection {
+        let (out, throughput) = self.reflect(at, inc, norm, rng);
+        super::Reflection { out, throughput }
+    }
+
+    fn get_photon_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, _uv: &UV, _wavelength: Wavelength, rng: &mut dyn RngCore) -> crate::light::Photon {
+        // Reflection alone is symmetric
+        let (ray, flux) = self.reflect(at, inc, norm, rng);
+        crate::light::Photon { ray, flux }
+    }
+}
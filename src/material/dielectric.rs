@@ -0,0 +1,79 @@
+use super::microfacet::{fresnel_dielectric, reflect, refract, Ggx};
+use crate::renderer::*;
+use crate::spectrum::{Ior, Wavelength};
+use rand::{Rng, RngCore};
+
+/**
+ * Rough glass with GGX microfacets, reflecting or refracting by the Fresnel reflectance of the sampled microfacet
+ */
+pub struct Dielectric {
+    // Outside and inside, the normal pointing inwards
+    n1: Ior,
+    n2: Ior,
+    throughput: Color,
+    absorption: Color,
+    distribution: Ggx,
+}
+
+impl Dielectric {
+    pub fn new(n1: Ior, n2: Ior, throughput: Color, roughness: f64) -> Self {
+        Self { n1, n2, throughput, absorption: Color::zeros(), distribution: Ggx::new(roughness) }
+    }
+
+    pub fn with_absorption(self, absorption: Color) -> Self {
+        Self { absorption, ..self }
+    }
+
+    /**
+     * Samples the outgoing ray, returning the throughput and the ratio of the IORs (incident / transmitted) if refracted
+     */
+    fn scatter(&self, at: &Point, inc: &Dir, norm: &Dir, wavelength: Wavelength, rng: &mut dyn RngCore) -> (Ray, Color, Option<f64>) {
+        let wi = -inc;
+        let entering = inc.dot(norm) > 0f64;
+        let (n1, n2) = (self.n1.at(wavelength), self.n2.at(wavelength));
+        let (normal, eta) = if entering { (-norm, n1 / n2) } else { (*norm, n2 / n1) };
+
+        let m = self.distribution.sample_visible(&wi, &normal, rng);
+        let reflectance = fresnel_dielectric(wi.dot(&m), eta);
+
+        let (wo, refracted) = match refract(&wi, &m, eta) {
+            Some(wo) if !rng.gen_bool(reflectance.clamp(0f64, 1f64)) => (wo, true),
+            _ => (reflect(&wi, &m), false),
+        };
+
+        // Must stay on the sampled side of the macro surface
+        if (wo.dot(&normal) > 0f64) == refracted {
+            return (Ray::new(*at, wo), Color::zeros(), None);
+        }
+
+        let throughput = self.throughput * self.distribution.shadowing_weight(&wi, &wo, &normal);
+        (Ray::new(*at, wo), throughput, if refracted { Some(eta) } else { None })
+    }
+}
+
+impl super::Material for Dielectric {
+    fn is_lambertian(&self) -> bool {
+        false
+    }
+    fn get_lambertian_ratio(&self, _uv: &UV) -> Color {
+        Color::zeros()
+    }
+
+    fn get_vision_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, _uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> super::Reflection {
+        let (out, throughput, eta) = self.scatter(at, inc, norm, wavelength, rng);
+
+        // Radiance over the squared IOR is invariant across the interface
+        let scale = eta.map(|eta| eta * eta).unwrap_or(1f64);
+        super::Reflection { out, throughput: throughput * scale }
+    }
+
+    fn get_photon_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, _uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> crate::light::Photon {
+        // Flux is conserved across the interface, unlike radiance
+        let (ray, flux, _) = self.scatter(at, inc, norm, wavelength, rng);
+        crate::light::Photon { ray, flux }
+    }
+
+    fn absorption(&self) -> Color {
+        self.absorption
+    }
+}
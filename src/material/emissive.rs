@@ -0,0 +1,46 @@
+use crate::renderer::*;
+use crate::spectrum::Wavelength;
+use rand::RngCore;
+
+/**
+ * Light emitting surface, absorbing everything it receives.
+ *
+ * Objects with this material should be registered as an `AreaLight` as well, so that they illuminate the scene.
+ */
+#[derive(Clone)]
+pub struct Emissive {
+    emission: Color,
+}
+
+impl Emissive {
+    pub fn new(emission: Color) -> Self {
+        Self { emission }
+    }
+}
+
+impl super::Material for Emissive {
+    fn is_lambertian(&self) -> bool {
+        false
+    }
+    fn get_lambertian_ratio(&self, _uv: &UV) -> Color {
+        Color::zeros()
+    }
+
+    fn get_vision_reflection(&self, at: &Point, inc: &Dir, _norm: &Dir, _uv: &UV, _wavelength: Wavelength, _rng: &mut dyn RngCore) -> super::Reflection {
+        super::Reflection {
+            out: Ray::new(*at, -inc),
+            throughput: Color::zeros(),
+        }
+    }
+
+    fn get_photon_reflection(&self, at: &Point, inc: &Dir, _norm: &Dir, _uv: &UV, _wavelength: Wavelength, _rng: &mut dyn RngCore) -> crate::light::Photon {
+        crate::light::Photon {
+            ray: Ray::new(*at, -inc),
+            flux: Color::zeros(),
+        }
+    }
+
+    fn emission(&self) -> Color {
+        self.emission
+    }
+}
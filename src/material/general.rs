@@ -1,10 +1,13 @@
 use crate::consts::*;
 use crate::renderer::*;
+use crate::spectrum::{Ior, Wavelength};
+use crate::texture::{NormalMap, Texture};
 use nalgebra::*;
 use crate::renderer::Point;
-use rand::rngs::ThreadRng;
+use rand::RngCore;
 use rand_distr::Normal;
 use rand::Rng;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct General {
@@ -16,10 +19,17 @@ pub struct General {
     diffusion_throughput: Vector3<f64>,
 
     specular_ratio: f64,
-    nratio: f64,
-    r0: f64,
+    n1: Ior,
+    n2: Ior,
 
     glossy_dist: Option<Normal<f64>>,
+
+    // Multiplied onto the throughputs above
+    refraction_texture: Option<Arc<dyn Texture>>,
+    diffusion_texture: Option<Arc<dyn Texture>>,
+    normal_map: Option<NormalMap>,
+
+    absorption: Color,
 }
 
 impl General {
@@ -45,14 +55,79 @@ impl General {
 
             specular_ratio: refraction_ratio + pure_reflection_ratio,
 
-            nratio: n1 / n2,
-            r0: ((n1 - n2) / (n1 + n2)).powi(2),
+            n1: Ior::Constant(n1),
+            n2: Ior::Constant(n2),
 
             glossy_dist: dist,
+
+            refraction_texture: None,
+            diffusion_texture: None,
+            normal_map: None,
+
+            absorption: Color::zeros(),
+        }
+    }
+
+    /**
+     * Varies the refraction throughput, which also applies to specular reflection, over the surface
+     */
+    pub fn with_refraction_texture(self, texture: Arc<dyn Texture>) -> Self {
+        Self {
+            refraction_texture: Some(texture),
+            ..self
+        }
+    }
+
+    /**
+     * Varies the diffusion throughput over the surface
+     */
+    pub fn with_diffusion_texture(self, texture: Arc<dyn Texture>) -> Self {
+        Self {
+            diffusion_texture: Some(texture),
+            ..self
+        }
+    }
+
+    /**
+     * Replaces the IORs given to `new`, e.g. with dispersive ones
+     */
+    pub fn with_ior(self, n1: Ior, n2: Ior) -> Self {
+        Self { n1, n2, ..self }
+    }
+
+    pub fn with_normal_map(self, map: NormalMap) -> Self {
+        Self {
+            normal_map: Some(map),
+            ..self
+        }
+    }
+
+    /**
+     * Attenuates light traveling inside by exp(-absorption * distance), unlike the throughputs applied per interface
+     */
+    pub fn with_absorption(self, absorption: Color) -> Self {
+        Self {
+            absorption,
+            ..self
+        }
+    }
+
+    /**
+     * Ratio of the IORs n1 / n2, and the reflectance at normal incidence
+     */
+    fn ior_ratio(&self, wavelength: Wavelength) -> (f64, f64) {
+        let (n1, n2) = (self.n1.at(wavelength), self.n2.at(wavelength));
+        (n1 / n2, ((n1 - n2) / (n1 + n2)).powi(2))
+    }
+
+    fn refraction_throughput(&self, uv: &UV) -> Vector3<f64> {
+        match &self.refraction_texture {
+            Some(t) => self.refraction_throughput.component_mul(&t.lookup(uv)),
+            None => self.refraction_throughput,
         }
     }
 
-    fn generate_reflection_ray(&self, at: &Point, inc: &Dir, norm: &Dir, _theta_i: f64, rng : &mut ThreadRng) -> super::Reflection {
+    fn generate_reflection_ray(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, _theta_i: f64, rng: &mut dyn RngCore) -> super::Reflection {
         let inc = -inc;
         let projected = inc.dot(norm);
         let mut scaled = norm.clone();
@@ -79,15 +154,16 @@ impl General {
 
         super::Reflection {
             out: Ray::new(at.clone(), out.clone()),
-            throughput: self.specular_ratio * self.refraction_throughput
+            throughput: self.specular_ratio * self.refraction_throughput(uv)
         }
     }
 
-    fn generate_refraction_ray(&self, at: &Point, inc: &Dir, norm: &Dir, theta_i: f64, rng: &mut ThreadRng) -> super::Reflection {
+    #[allow(clippy::too_many_arguments)]
+    fn generate_refraction_ray(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, theta_i: f64, nratio: f64, rng: &mut dyn RngCore) -> super::Reflection {
         let (theta_t_sin, starting_norm, negate) = if theta_i < std::f64::consts::PI / 2f64 { // Outgoing
-            (theta_i.sin() / self.nratio, -norm, false)
+            (theta_i.sin() / nratio, -norm, false)
         } else {
-            (theta_i.sin() * self.nratio, norm.clone(), true)
+            (theta_i.sin() * nratio, norm.clone(), true)
         };
 
         let theta_t = theta_t_sin.asin();
@@ -106,10 +182,11 @@ impl General {
                 throughput: Vector3::new(0f64, 0f64, 0f64),
             };
             */
-            return self.generate_reflection_ray(at, inc, norm, theta_i, rng);
+            return self.generate_reflection_ray(at, inc, norm, uv, theta_i, rng);
         }
 
-        if theta_t <= 0f64 {
+        // Zero at normal incidence, passing straight through
+        if theta_t < 0f64 {
             log::error!("Unexpected theta_t {} from sin {}", theta_t, theta_t_sin);
             loop {}
         }
@@ -153,7 +230,7 @@ impl General {
 
         super::Reflection {
             out: Ray::new(at.clone(), out.clone()),
-            throughput: self.specular_ratio * self.refraction_throughput
+            throughput: self.specular_ratio * self.refraction_throughput(uv)
         }
     }
 }
@@ -162,14 +239,19 @@ impl super::Material for General {
     fn is_lambertian(&self) -> bool {
         self.diffusion_ratio > EPS
     }
-    fn get_lambertian_ratio(&self) -> Vector3<f64> {
-        self.diffusion_throughput.clone_owned() * self.diffusion_ratio
+    fn get_lambertian_ratio(&self, uv: &UV) -> Vector3<f64> {
+        let throughput = match &self.diffusion_texture {
+            Some(t) => self.diffusion_throughput.component_mul(&t.lookup(uv)),
+            None => self.diffusion_throughput,
+        };
+        throughput * self.diffusion_ratio
     }
 
     // Normal is n2 -> n1
-    fn get_vision_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, rng: &mut ThreadRng) -> super::Reflection {
+    fn get_vision_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> super::Reflection {
         let theta_i: f64 = inc.angle(&-norm).into();
-        let reflection_coeff = self.r0 + (1f64 - self.r0) * (1f64 - theta_i.cos().abs()).powi(5);
+        let (nratio, r0) = self.ior_ratio(wavelength);
+        let reflection_coeff = r0 + (1f64 - r0) * (1f64 - theta_i.cos().abs()).powi(5);
         // let reflection_coeff = 0f64;
         let reflected_ratio = reflection_coeff * self.refraction_ratio + self.pure_reflection_ratio;
 
@@ -184,19 +266,27 @@ impl super::Material for General {
         let is_reflection = rng.gen_bool(reflected_ratio / self.specular_ratio);
 
         if is_reflection {
-            self.generate_reflection_ray(at, inc, norm, theta_i, rng)
+            self.generate_reflection_ray(at, inc, norm, uv, theta_i, rng)
         } else {
-            self.generate_refraction_ray(at, inc, norm, theta_i, rng)
+            self.generate_refraction_ray(at, inc, norm, uv, theta_i, nratio, rng)
         }
     }
 
-    fn get_photon_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, rng: &mut ThreadRng) -> crate::light::Photon {
+    fn get_photon_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> crate::light::Photon {
         // Identical to vision reflection
-        let reflection = self.get_vision_reflection(at, inc, norm, rng);
+        let reflection = self.get_vision_reflection(at, inc, norm, uv, wavelength, rng);
 
         crate::light::Photon {
             ray: reflection.out,
             flux: reflection.throughput,
         }
     }
+
+    fn absorption(&self) -> Color {
+        self.absorption
+    }
+
+    fn normal_map(&self) -> Option<&NormalMap> {
+        self.normal_map.as_ref()
+    }
 }
@@ -0,0 +1,136 @@
+use crate::light::tangents;
+use crate::renderer::*;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+
+// Lower bound of alpha, keeping smooth surfaces numerically stable
+const MIN_ALPHA: f64 = 1e-4;
+
+/**
+ * Isotropic GGX / Trowbridge-Reitz distribution of microfacet normals.
+ *
+ * Directions are in world space, with `normal` on the side of the incoming light or sight.
+ */
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /**
+     * `roughness` is perceptual, alpha = roughness^2
+     */
+    pub fn new(roughness: f64) -> Self {
+        Self { alpha: (roughness * roughness).max(MIN_ALPHA) }
+    }
+
+    /**
+     * Smith's auxiliary function for a direction `w` against the macro normal
+     */
+    fn lambda(&self, w: &Dir, normal: &Dir) -> f64 {
+        let cos = w.dot(normal).abs().min(1f64);
+        if cos <= 0f64 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1f64 - cos * cos).max(0f64) / (cos * cos);
+        ((1f64 + self.alpha * self.alpha * tan2).sqrt() - 1f64) / 2f64
+    }
+
+    /**
+     * Weight of a direction sampled with `sample_visible` from `wi`, reaching `wo`: G2(wi, wo) / G1(wi),
+     * height correlated
+     */
+    pub fn shadowing_weight(&self, wi: &Dir, wo: &Dir, normal: &Dir) -> f64 {
+        let lambda_i = self.lambda(wi, normal);
+        let lambda_o = self.lambda(wo, normal);
+        if !lambda_o.is_finite() {
+            return 0f64;
+        }
+        (1f64 + lambda_i) / (1f64 + lambda_i + lambda_o)
+    }
+
+    /**
+     * Samples a microfacet normal visible from `wi`, which points away from the surface.
+     * See Heitz, "Sampling the GGX Distribution of Visible Normals", 2018
+     */
+    pub fn sample_visible(&self, wi: &Dir, normal: &Dir, rng: &mut dyn RngCore) -> Dir {
+        let (tangent, bitangent) = tangents(normal);
+        let local = Dir::new(wi.dot(&tangent), wi.dot(&bitangent), wi.dot(normal).max(0f64));
+
+        // Stretched to the hemisphere configuration
+        let vh = Dir::new(self.alpha * local[0], self.alpha * local[1], local[2]).normalize();
+        let lensq = vh[0] * vh[0] + vh[1] * vh[1];
+        let t1 = if lensq > 0f64 { Dir::new(-vh[1], vh[0], 0f64) / lensq.sqrt() } else { Dir::new(1f64, 0f64, 0f64) };
+        let t2 = vh.cross(&t1);
+
+        let r = rng.gen::<f64>().sqrt();
+        let phi = 2f64 * PI * rng.gen::<f64>();
+        let p1 = r * phi.cos();
+        let s = (1f64 + vh[2]) / 2f64;
+        let p2 = (1f64 - s) * (1f64 - p1 * p1).max(0f64).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1f64 - p1 * p1 - p2 * p2).max(0f64).sqrt();
+
+        let m = Dir::new(self.alpha * nh[0], self.alpha * nh[1], nh[2].max(0f64)).normalize();
+        (tangent * m[0] + bitangent * m[1] + normal * m[2]).normalize()
+    }
+}
+
+/**
+ * Mirrors `wi`, pointing away from the surface, around `m`
+ */
+pub fn reflect(wi: &Dir, m: &Dir) -> Dir {
+    m * (2f64 * wi.dot(m)) - wi
+}
+
+/**
+ * Refracts `wi`, pointing away from the surface on the side of `m`, with `eta` = eta_i / eta_t.
+ * None on total internal reflection
+ */
+pub fn refract(wi: &Dir, m: &Dir, eta: f64) -> Option<Dir> {
+    let cos_i = wi.dot(m);
+    let sin2_t = eta * eta * (1f64 - cos_i * cos_i).max(0f64);
+    if sin2_t >= 1f64 {
+        return None;
+    }
+    let cos_t = (1f64 - sin2_t).sqrt();
+    Some((-wi * eta + m * (eta * cos_i - cos_t)).normalize())
+}
+
+/**
+ * Unpolarized Fresnel reflectance between dielectrics, with `eta` = eta_i / eta_t
+ */
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.abs().min(1f64);
+    let sin2_t = eta * eta * (1f64 - cos_i * cos_i);
+    if sin2_t >= 1f64 {
+        return 1f64;
+    }
+    let cos_t = (1f64 - sin2_t).sqrt();
+
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (rs * rs + rp * rp) / 2f64
+}
+
+/**
+ * Unpolarized Fresnel reflectance from a dielectric onto a conductor with complex IOR `eta` + i `k`,
+ * both relative to the dielectric, for each channel
+ */
+pub fn fresnel_conductor(cos_i: f64, eta: &Color, k: &Color) -> Color {
+    let cos2 = cos_i.abs().min(1f64).powi(2);
+    let sin2 = 1f64 - cos2;
+
+    Color::from_fn(|c, _| {
+        let (eta2, k2) = (eta[c] * eta[c], k[c] * k[c]);
+        let t0 = eta2 - k2 - sin2;
+        let a2b2 = (t0 * t0 + 4f64 * eta2 * k2).sqrt();
+        let t1 = a2b2 + cos2;
+        let a = ((a2b2 + t0) / 2f64).max(0f64).sqrt();
+        let t2 = 2f64 * cos2.sqrt() * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        (rs + rp) / 2f64
+    })
+}
@@ -1,9 +1,15 @@
 use super::renderer::*;
+use super::spectrum::Wavelength;
+use super::texture::NormalMap;
 use nalgebra::Vector3;
 
-use rand::rngs::ThreadRng;
+use rand::RngCore;
 
+pub mod conductor;
+pub mod dielectric;
+pub mod emissive;
 pub mod general;
+pub mod microfacet;
 
 /**
  * Both reflection and refrection
@@ -13,13 +19,62 @@ pub struct Reflection {
     pub throughput: Vector3<f64>,
 }
 
+/**
+ * Surface properties may vary with the texture coordinates `uv` of the hit point. Colors are returned in RGB
+ * even in the spectral mode, where `wavelength` only affects the directions, e.g. through dispersion
+ */
 pub trait Material : Sync + Send {
     fn is_lambertian(&self) -> bool;
-    fn get_lambertian_ratio(&self) -> Vector3<f64>;
+    fn get_lambertian_ratio(&self, uv: &UV) -> Vector3<f64>;
 
     // Specular
-    fn get_vision_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, rng: &mut ThreadRng) -> Reflection;
+    fn get_vision_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> Reflection;
 
     // Specular
-    fn get_photon_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, rng: &mut ThreadRng) -> super::light::Photon;
+    fn get_photon_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> super::light::Photon;
+
+    /**
+     * Exitance (emitted flux per unit area) from the side opposite to the normal, i.e. the outside of closed objects
+     */
+    fn emission(&self) -> Color {
+        Color::zeros()
+    }
+
+    /**
+     * Absorption coefficient per unit distance inside the object, attenuating rays refracted into it
+     */
+    fn absorption(&self) -> Color {
+        Color::zeros()
+    }
+
+    /**
+     * Applied to the shading normal before any other method is called
+     */
+    fn normal_map(&self) -> Option<&NormalMap> {
+        None
+    }
+}
+
+impl<T> Material for T where T: AsRef<dyn Material> + Send + Sync {
+    fn is_lambertian(&self) -> bool {
+        self.as_ref().is_lambertian()
+    }
+    fn get_lambertian_ratio(&self, uv: &UV) -> Vector3<f64> {
+        self.as_ref().get_lambertian_ratio(uv)
+    }
+    fn get_vision_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> Reflection {
+        self.as_ref().get_vision_reflection(at, inc, norm, uv, wavelength, rng)
+    }
+    fn get_photon_reflection(&self, at: &Point, inc: &Dir, norm: &Dir, uv: &UV, wavelength: Wavelength, rng: &mut dyn RngCore) -> super::light::Photon {
+        self.as_ref().get_photon_reflection(at, inc, norm, uv, wavelength, rng)
+    }
+    fn emission(&self) -> Color {
+        self.as_ref().emission()
+    }
+    fn absorption(&self) -> Color {
+        self.as_ref().absorption()
+    }
+    fn normal_map(&self) -> Option<&NormalMap> {
+        self.as_ref().normal_map()
+    }
 }
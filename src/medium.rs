@@ -0,0 +1,413 @@
+use crate::object::BoundingBox;
+use crate::phase::{Isotropic, PhaseFunction};
+use crate::renderer::*;
+use rand::{Rng, RngCore};
+use std::path::Path;
+use std::sync::Arc;
+
+/**
+ * Density varying within the bounds of a medium, in local coordinates spanning [0, 1] on every axis
+ */
+pub trait Density: Send + Sync {
+    fn lookup(&self, local: &Point) -> f64;
+
+    /// Upper bound of `lookup`, the majorant for delta tracking
+    fn max(&self) -> f64;
+}
+
+/**
+ * Uniform density of 1, filling the bounds
+ */
+pub struct ConstantDensity;
+
+impl Density for ConstantDensity {
+    fn lookup(&self, _local: &Point) -> f64 {
+        1f64
+    }
+
+    fn max(&self) -> f64 {
+        1f64
+    }
+}
+
+/**
+ * Voxel grid stretched over the bounds, with voxel centers at half integers. Trilinearly filtered
+ */
+pub struct GridDensity {
+    resolution: [usize; 3],
+    /// X varies the fastest, then Y
+    values: Vec<f64>,
+    max: f64,
+}
+
+impl GridDensity {
+    /**
+     * Loads a single channel, float32 grid volume in the binary format of Mitsuba (`.vol`, version 3).
+     * The bounding box stored in the file is ignored. Negative values are clamped to 0
+     */
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = std::fs::read(path)?;
+        if data.len() < 48 || &data[0..3] != b"VOL" || data[3] != 3 {
+            return Err("not a version 3 .vol file".into());
+        }
+
+        let int = |offset: usize| i32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        if int(4) != 1 {
+            return Err(format!("unsupported encoding {}, expected 1 (float32)", int(4)).into());
+        }
+        if int(20) != 1 {
+            return Err(format!("expected a single channel, got {}", int(20)).into());
+        }
+        if int(8) <= 0 || int(12) <= 0 || int(16) <= 0 {
+            return Err(format!("invalid resolution {}x{}x{}", int(8), int(12), int(16)).into());
+        }
+
+        let resolution = [int(8) as usize, int(12) as usize, int(16) as usize];
+        let count = resolution[0] * resolution[1] * resolution[2];
+        let body = &data[48..];
+        if body.len() < count * 4 {
+            return Err(format!("expected {} voxels, the file is truncated", count).into());
+        }
+
+        let values: Vec<f64> = body
+            .chunks_exact(4)
+            .take(count)
+            .map(|b| (f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64).max(0f64))
+            .collect();
+        if values.iter().any(|v| !v.is_finite()) {
+            return Err("voxels must be finite".into());
+        }
+        let max = values.iter().cloned().fold(0f64, f64::max);
+
+        Ok(Self { resolution, values, max })
+    }
+
+    fn voxel(&self, x: i64, y: i64, z: i64) -> f64 {
+        let clamp = |v: i64, axis: usize| v.clamp(0, self.resolution[axis] as i64 - 1) as usize;
+        let (x, y, z) = (clamp(x, 0), clamp(y, 1), clamp(z, 2));
+        self.values[(z * self.resolution[1] + y) * self.resolution[0] + x]
+    }
+}
+
+impl Density for GridDensity {
+    fn lookup(&self, local: &Point) -> f64 {
+        let p = Point::from_fn(|axis, _| local[axis] * self.resolution[axis] as f64 - 0.5f64);
+        let base = p.map(f64::floor);
+        let f = p - base;
+        let (x, y, z) = (base[0] as i64, base[1] as i64, base[2] as i64);
+
+        let mut value = 0f64;
+        for corner in 0..8 {
+            let (dx, dy, dz) = (corner & 1, (corner >> 1) & 1, corner >> 2);
+            let weight = (if dx == 1 { f[0] } else { 1f64 - f[0] })
+                * (if dy == 1 { f[1] } else { 1f64 - f[1] })
+                * (if dz == 1 { f[2] } else { 1f64 - f[2] });
+            value += self.voxel(x + dx, y + dy, z + dz) * weight;
+        }
+        value
+    }
+
+    fn max(&self) -> f64 {
+        self.max
+    }
+}
+
+/**
+ * Fractal gradient noise, with `frequency` cells across the bounds at the coarsest of `octaves`.
+ * Noise values below `threshold` are empty, and the rest are rescaled to [0, 1]
+ */
+pub struct NoiseDensity {
+    frequency: f64,
+    octaves: usize,
+    threshold: f64,
+    seed: u32,
+}
+
+impl NoiseDensity {
+    pub fn new(frequency: f64, octaves: usize, threshold: f64, seed: u32) -> Self {
+        Self { frequency, octaves, threshold, seed }
+    }
+
+    /**
+     * One of the 12 edge directions of the cube, hashed from the lattice point
+     */
+    fn gradient(&self, x: i64, y: i64, z: i64) -> Dir {
+        let mut h = self.seed.wrapping_mul(0x9e37_79b9) ^ (x as u32).wrapping_mul(0x85eb_ca6b);
+        h = (h ^ (h >> 15)) ^ (y as u32).wrapping_mul(0xc2b2_ae35);
+        h = (h ^ (h >> 13)) ^ (z as u32).wrapping_mul(0x27d4_eb2f);
+        h = (h ^ (h >> 16)).wrapping_mul(0x7feb_352d);
+        h ^= h >> 15;
+
+        let (a, b) = (if h & 1 == 0 { 1f64 } else { -1f64 }, if h & 2 == 0 { 1f64 } else { -1f64 });
+        match (h >> 2) % 3 {
+            0 => Dir::new(a, b, 0f64),
+            1 => Dir::new(a, 0f64, b),
+            _ => Dir::new(0f64, a, b),
+        }
+    }
+
+    /**
+     * Perlin's gradient noise, roughly within [-1, 1]
+     */
+    fn noise(&self, p: &Point) -> f64 {
+        let base = p.map(f64::floor);
+        let f = p - base;
+        let fade = f.map(|t| t * t * t * (t * (t * 6f64 - 15f64) + 10f64));
+        let (x, y, z) = (base[0] as i64, base[1] as i64, base[2] as i64);
+
+        let mut value = 0f64;
+        for corner in 0..8 {
+            let (dx, dy, dz) = (corner & 1, (corner >> 1) & 1, corner >> 2);
+            let offset = f - Dir::new(dx as f64, dy as f64, dz as f64);
+            let weight = (if dx == 1 { fade[0] } else { 1f64 - fade[0] })
+                * (if dy == 1 { fade[1] } else { 1f64 - fade[1] })
+                * (if dz == 1 { fade[2] } else { 1f64 - fade[2] });
+            value += self.gradient(x + dx, y + dy, z + dz).dot(&offset) * weight;
+        }
+        value
+    }
+}
+
+impl Density for NoiseDensity {
+    fn lookup(&self, local: &Point) -> f64 {
+        let (mut sum, mut total, mut amplitude, mut frequency) = (0f64, 0f64, 1f64, self.frequency);
+        for _octave in 0..self.octaves {
+            sum += self.noise(&(local * frequency)) * amplitude;
+            total += amplitude;
+            amplitude /= 2f64;
+            frequency *= 2f64;
+        }
+        ((sum / total - self.threshold) / (1f64 - self.threshold)).clamp(0f64, 1f64)
+    }
+
+    fn max(&self) -> f64 {
+        1f64
+    }
+}
+
+/**
+ * Homogeneous medium filling the interior of an object, in place of the global fog and the bounded media
+ */
+#[derive(Clone)]
+pub struct InteriorMedium {
+    /// Per unit length, for each channel (Beer-Lambert law)
+    pub absorption: Color,
+    /// Interactions per unit length
+    pub scattering: f64,
+    pub phase: Arc<dyn PhaseFunction>,
+    /// Fraction of the interactions scattering photons on, instead of absorbing them
+    pub albedo: f64,
+}
+
+impl InteriorMedium {
+    pub fn new(absorption: Color, scattering: f64) -> Self {
+        Self {
+            absorption,
+            scattering,
+            phase: Arc::new(Isotropic),
+            albedo: 0f64,
+        }
+    }
+
+    pub fn with_phase(self, phase: Arc<dyn PhaseFunction>, albedo: f64) -> Self {
+        Self { phase, albedo, ..self }
+    }
+}
+
+/**
+ * Participating medium within an axis-aligned box. Photons interact at a rate of `scattering` times the density per
+ * unit length
+ */
+pub struct Medium {
+    bounds: BoundingBox,
+    density: Arc<dyn Density>,
+    scattering: f64,
+    majorant: f64,
+    phase: Arc<dyn PhaseFunction>,
+    albedo: f64,
+}
+
+impl Medium {
+    pub fn new(bounds: BoundingBox, density: Arc<dyn Density>, scattering: f64) -> Self {
+        let majorant = scattering * density.max();
+        Self {
+            bounds,
+            density,
+            scattering,
+            majorant,
+            phase: Arc::new(Isotropic),
+            albedo: 0f64,
+        }
+    }
+
+    /**
+     * Scatters `albedo` of the photons interacting by `phase`, instead of absorbing all of them
+     */
+    pub fn with_phase(self, phase: Arc<dyn PhaseFunction>, albedo: f64) -> Self {
+        Self { phase, albedo, ..self }
+    }
+
+    /**
+     * Interaction rate at `at`, zero outside the bounds
+     */
+    pub fn coefficient(&self, at: &Point) -> f64 {
+        let local = Point::from_fn(|axis, _| {
+            let (lower, upper) = self.bounds.axis(axis);
+            (at[axis] - lower) / (upper - lower)
+        });
+        if local.iter().any(|c| !(0f64..=1f64).contains(c)) {
+            return 0f64;
+        }
+        self.scattering * self.density.lookup(&local)
+    }
+
+    /**
+     * Distance to the first interaction along the ray before `dist`, with delta tracking
+     */
+    pub fn interaction(&self, ray: &Ray, dist: f64, rng: &mut dyn RngCore) -> Option<f64> {
+        if self.majorant <= 0f64 {
+            return None;
+        }
+        let (mut t, exit) = self.bounds.clip(ray, Some(dist))?;
+        loop {
+            t -= (1f64 - rng.gen::<f64>()).ln() / self.majorant;
+            if t >= exit {
+                return None;
+            }
+            if rng.gen::<f64>() * self.majorant < self.coefficient(&ray.interpolate(t)) {
+                return Some(t);
+            }
+        }
+    }
+
+    /**
+     * Unbiased estimate of the fraction of photons passing the first `dist` units of the ray, with ratio tracking
+     */
+    pub fn transmittance(&self, ray: &Ray, dist: f64, rng: &mut dyn RngCore) -> f64 {
+        if self.majorant <= 0f64 {
+            return 1f64;
+        }
+        let (mut t, exit) = match self.bounds.clip(ray, Some(dist)) {
+            Some(range) => range,
+            None => return 1f64,
+        };
+
+        let mut transmittance = 1f64;
+        loop {
+            t -= (1f64 - rng.gen::<f64>()).ln() / self.majorant;
+            if t >= exit {
+                return transmittance;
+            }
+            transmittance *= 1f64 - self.coefficient(&ray.interpolate(t)) / self.majorant;
+        }
+    }
+}
+
+/**
+ * Bounded media in the scene, in addition to the global fog. Where they overlap, the rates add up
+ */
+#[derive(Default)]
+pub struct Media {
+    media: Vec<Medium>,
+}
+
+impl Media {
+    pub fn new(media: Vec<Medium>) -> Self {
+        Self { media }
+    }
+
+    /**
+     * Adds the media interacting at `at` to `mixture`
+     */
+    pub fn mix_into<'a>(&'a self, at: &Point, mixture: &mut PhaseMixture<'a>) {
+        for medium in self.media.iter() {
+            mixture.add(medium.coefficient(at), medium.phase.as_ref(), medium.albedo);
+        }
+    }
+
+    /**
+     * Distance to the first interaction with any of the media before `dist`
+     */
+    pub fn interaction(&self, ray: &Ray, dist: f64, rng: &mut dyn RngCore) -> Option<f64> {
+        self.media
+            .iter()
+            .filter_map(|m| m.interaction(ray, dist, rng))
+            .fold(None, |nearest: Option<f64>, t| Some(nearest.map_or(t, |n| n.min(t))))
+    }
+
+    pub fn transmittance(&self, ray: &Ray, dist: f64, rng: &mut dyn RngCore) -> f64 {
+        self.media.iter().map(|m| m.transmittance(ray, dist, rng)).product()
+    }
+}
+
+/**
+ * Phase functions and albedos of the media overlapping at a point, weighted by their interaction rates there
+ */
+#[derive(Default)]
+pub struct PhaseMixture<'a> {
+    components: Vec<(f64, &'a dyn PhaseFunction, f64)>,
+}
+
+impl<'a> PhaseMixture<'a> {
+    pub fn add(&mut self, rate: f64, phase: &'a dyn PhaseFunction, albedo: f64) {
+        if rate > 0f64 {
+            self.components.push((rate, phase, albedo));
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        self.components.iter().map(|(rate, _, _)| rate).sum()
+    }
+
+    /**
+     * Density of scattering by the angle whose cosine is `cos`, isotropic without any media
+     */
+    pub fn eval(&self, cos: f64) -> f64 {
+        let rate = self.rate();
+        if rate <= 0f64 {
+            return Isotropic.eval(cos);
+        }
+        self.components.iter().map(|(r, phase, _)| r * phase.eval(cos)).sum::<f64>() / rate
+    }
+
+    /**
+     * Probability of an interaction scattering the photon on
+     */
+    pub fn albedo(&self) -> f64 {
+        let rate = self.rate();
+        if rate <= 0f64 {
+            return 0f64;
+        }
+        self.components.iter().map(|(r, _, albedo)| r * albedo).sum::<f64>() / rate
+    }
+
+    /**
+     * Density of `sample` choosing the direction by the angle whose cosine is `cos`
+     */
+    pub fn scattered(&self, cos: f64) -> f64 {
+        let total: f64 = self.components.iter().map(|(r, _, albedo)| r * albedo).sum();
+        if total <= 0f64 {
+            return 0f64;
+        }
+        self.components.iter().map(|(r, phase, albedo)| r * albedo * phase.eval(cos)).sum::<f64>() / total
+    }
+
+    /**
+     * Samples the direction after scattering a ray along `dir`, by the medium chosen proportionally to the rate of
+     * scattering rather than absorbing
+     */
+    pub fn sample(&self, dir: &Dir, rng: &mut dyn RngCore) -> Dir {
+        let total: f64 = self.components.iter().map(|(r, _, albedo)| r * albedo).sum();
+        let mut pick = rng.gen::<f64>() * total;
+        for (r, phase, albedo) in self.components.iter() {
+            pick -= r * albedo;
+            if pick < 0f64 {
+                return phase.sample(dir, rng);
+            }
+        }
+        match self.components.last() {
+            Some((_, phase, _)) => phase.sample(dir, rng),
+            None => Isotropic.sample(dir, rng),
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use super::BoundingBox;
+use crate::renderer::*;
+use log::info;
+
+const BIN_COUNT: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f64 = 0.125;
+
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    // The first child is always placed right after its parent
+    Inner { second: usize },
+}
+
+struct Node {
+    bb: BoundingBox,
+    kind: NodeKind,
+}
+
+struct BuildItem {
+    index: usize,
+    bb: BoundingBox,
+    centroid: Point,
+}
+
+#[derive(Default)]
+struct BuildStat {
+    leaves: usize,
+    max_depth: usize,
+    max_leaf_size: usize,
+}
+
+/**
+ * Bounding volume hierarchy, built with binned SAH
+ */
+pub struct Bvh<T> {
+    nodes: Vec<Node>,
+    items: Vec<(T, BoundingBox)>,
+}
+
+impl<T> Bvh<T> {
+    pub fn new(input: Vec<(T, BoundingBox)>) -> Self {
+        let start = std::time::Instant::now();
+
+        let mut build_items: Vec<BuildItem> = input.iter().enumerate().map(|(index, (_, bb))| BuildItem {
+            index,
+            bb: *bb,
+            centroid: bb.centroid(),
+        }).collect();
+
+        let mut nodes = Vec::new();
+        let mut stat = BuildStat::default();
+        if !build_items.is_empty() {
+            Self::build(&mut nodes, &mut build_items, 0, 0, &mut stat);
+        }
+
+        // Reorder items so that every leaf covers a consecutive range
+        let mut slots: Vec<Option<(T, BoundingBox)>> = input.into_iter().map(Some).collect();
+        let items = build_items.iter().map(|bi| slots[bi.index].take().unwrap()).collect::<Vec<_>>();
+
+        if !items.is_empty() {
+            let cost = Self::sah_cost(&nodes, 0);
+            info!(
+                "BVH built in {:?}: {} items, {} nodes, {} leaves, max depth {}, max leaf size {}, SAH cost {:.2}",
+                start.elapsed(), items.len(), nodes.len(), stat.leaves, stat.max_depth, stat.max_leaf_size, cost
+            );
+        }
+
+        Self { nodes, items }
+    }
+
+    fn build(nodes: &mut Vec<Node>, items: &mut [BuildItem], offset: usize, depth: usize, stat: &mut BuildStat) {
+        let bb = items.iter().fold(BoundingBox::empty(), |acc, i| acc.merge(&i.bb));
+        let current = nodes.len();
+        nodes.push(Node {
+            bb,
+            kind: NodeKind::Leaf { first: offset, count: items.len() },
+        });
+        stat.max_depth = stat.max_depth.max(depth);
+
+        let mid = if items.len() <= 2 { None } else { Self::split(items, &bb) };
+
+        let mid = match mid {
+            Some(mid) => mid,
+            None => {
+                stat.leaves += 1;
+                stat.max_leaf_size = stat.max_leaf_size.max(items.len());
+                return;
+            }
+        };
+
+        let (left, right) = items.split_at_mut(mid);
+        Self::build(nodes, left, offset, depth + 1, stat);
+        let second = nodes.len();
+        Self::build(nodes, right, offset + mid, depth + 1, stat);
+        nodes[current].kind = NodeKind::Inner { second };
+    }
+
+    /**
+     * Partitions the items by the best SAH split, returning the size of the first half.
+     * Returns None if the items should stay in one leaf.
+     */
+    fn split(items: &mut [BuildItem], bb: &BoundingBox) -> Option<usize> {
+        let centroid_bb = items.iter().fold(BoundingBox::empty(), |acc, i| acc.merge(&BoundingBox::point(&i.centroid)));
+        let extent = centroid_bb.extent();
+        let axis = (0..3).max_by(|a, b| extent[*a].partial_cmp(&extent[*b]).unwrap()).unwrap();
+        let lower = centroid_bb.axis(axis).0;
+
+        if !extent[axis].is_finite() || extent[axis] <= 0f64 {
+            // All centroids coincide, nothing to split
+            return None;
+        }
+
+        let bin_of = |c: &Point| -> usize {
+            let b = ((c[axis] - lower) / extent[axis] * BIN_COUNT as f64) as usize;
+            b.min(BIN_COUNT - 1)
+        };
+
+        let mut bins = [(BoundingBox::empty(), 0usize); BIN_COUNT];
+        for item in items.iter() {
+            let bin = &mut bins[bin_of(&item.centroid)];
+            bin.0 = bin.0.merge(&item.bb);
+            bin.1 += 1;
+        }
+
+        // Sweep from the right side to get the area & count for each suffix
+        let mut suffix = [(0f64, 0usize); BIN_COUNT];
+        let mut acc = (BoundingBox::empty(), 0usize);
+        for i in (1..BIN_COUNT).rev() {
+            acc = (acc.0.merge(&bins[i].0), acc.1 + bins[i].1);
+            suffix[i] = (acc.0.surface_area(), acc.1);
+        }
+
+        let parent_area = bb.surface_area();
+        let mut best: Option<(usize, f64)> = None;
+        let mut acc = (BoundingBox::empty(), 0usize);
+        for i in 0..BIN_COUNT - 1 {
+            acc = (acc.0.merge(&bins[i].0), acc.1 + bins[i].1);
+            let (right_area, right_count) = suffix[i + 1];
+            if acc.1 == 0 || right_count == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + (acc.0.surface_area() * acc.1 as f64 + right_area * right_count as f64) / parent_area;
+            if best.map(|(_, c)| cost < c).unwrap_or(true) {
+                best = Some((i, cost));
+            }
+        }
+
+        let (split_bin, cost) = best?;
+        if cost >= items.len() as f64 && items.len() <= MAX_LEAF_SIZE {
+            return None;
+        }
+
+        let mut mid = 0;
+        for i in 0..items.len() {
+            if bin_of(&items[i].centroid) <= split_bin {
+                items.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        Some(mid)
+    }
+
+    fn sah_cost(nodes: &[Node], idx: usize) -> f64 {
+        let node = &nodes[idx];
+        match node.kind {
+            NodeKind::Leaf { count, .. } => count as f64,
+            NodeKind::Inner { second } => {
+                let area = node.bb.surface_area();
+                let left = &nodes[idx + 1];
+                let right = &nodes[second];
+                TRAVERSAL_COST
+                    + (left.bb.surface_area() * Self::sah_cost(nodes, idx + 1)
+                        + right.bb.surface_area() * Self::sah_cost(nodes, second))
+                        / area
+            }
+        }
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        self.nodes.first().map(|n| n.bb).unwrap_or_else(BoundingBox::empty)
+    }
+
+    /**
+     * Finds the closest hit. `test` intersects a single item, and `dist` extracts the distance from a hit.
+     */
+    pub fn intersect<'a, R>(
+        &'a self,
+        ray: &Ray,
+        mut upper: Option<f64>,
+        mut test: impl FnMut(&'a T, Option<f64>) -> Option<R>,
+        dist: impl Fn(&R) -> f64,
+    ) -> Option<R> {
+        let mut result = None;
+
+        let entry = self.nodes.first().and_then(|n| n.bb.hit(ray, upper))?;
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push((0, entry));
+
+        while let Some((idx, entry)) = stack.pop() {
+            // Skip nodes behind the closest hit found so far
+            if upper.map(|u| u < entry).unwrap_or(false) {
+                continue;
+            }
+
+            let node = &self.nodes[idx];
+
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    for (item, bb) in self.items[first..first + count].iter() {
+                        if bb.hit(ray, upper).is_none() {
+                            continue;
+                        }
+
+                        if let Some(hit) = test(item, upper) {
+                            upper = Some(dist(&hit));
+                            result = Some(hit);
+                        }
+                    }
+                }
+                NodeKind::Inner { second } => {
+                    let left = self.nodes[idx + 1].bb.hit(ray, upper).map(|t| (idx + 1, t));
+                    let right = self.nodes[second].bb.hit(ray, upper).map(|t| (second, t));
+
+                    // Visit the nearer child first
+                    match (left, right) {
+                        (Some(l), Some(r)) => {
+                            let (near, far) = if l.1 <= r.1 { (l, r) } else { (r, l) };
+                            stack.push(far);
+                            stack.push(near);
+                        }
+                        (Some(l), None) => stack.push(l),
+                        (None, Some(r)) => stack.push(r),
+                        (None, None) => {}
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::geometry::sphere::Sphere;
+    use crate::object::geometry::Geometry;
+    use rand::Rng;
+
+    fn random_point(rng: &mut RenderRng, scale: f64) -> Point {
+        Point::new(rng.gen_range(-scale, scale), rng.gen_range(-scale, scale), rng.gen_range(-scale, scale))
+    }
+
+    #[test]
+    fn intersects_like_a_linear_scan() {
+        let mut rng = task_rng(7, 0, 0);
+        let spheres: Vec<Sphere> = (0..200)
+            .map(|_| Sphere::new(random_point(&mut rng, 50f64), rng.gen_range(0.5f64, 4f64)))
+            .collect();
+        let bvh = Bvh::new(spheres.iter().map(|s| (s.clone(), s.bounding_box())).collect());
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(random_point(&mut rng, 60f64), random_point(&mut rng, 1f64));
+            let upper = if rng.gen_bool(0.5f64) { Some(rng.gen_range(1f64, 100f64)) } else { None };
+
+            let expected = spheres
+                .iter()
+                .filter_map(|s| s.intersect(&ray, upper))
+                .map(|hit| hit.dist)
+                .min_by(|a, b| a.total_cmp(b));
+            let found = bvh.intersect(&ray, upper, |s, upper| s.intersect(&ray, upper), |hit| hit.dist).map(|hit| hit.dist);
+
+            assert_eq!(found, expected, "ray {:?} up to {:?}", ray, upper);
+            hits += found.is_some() as usize;
+        }
+        assert!(hits > 100, "only {} rays hit anything", hits);
+    }
+
+    #[test]
+    fn empty_hierarchy_hits_nothing() {
+        let bvh: Bvh<Sphere> = Bvh::new(Vec::new());
+        let ray = Ray::new(Point::zeros(), Dir::new(0f64, 0f64, 1f64));
+        assert!(bvh.intersect(&ray, None, |s, upper| s.intersect(&ray, upper), |hit| hit.dist).is_none());
+    }
+}
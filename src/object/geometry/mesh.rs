@@ -0,0 +1,158 @@
+use super::triangle::{intersect_barycentric, uv_derivatives};
+use crate::consts::EPS;
+use crate::object::bvh::Bvh;
+use crate::object::BoundingBox;
+use crate::renderer::*;
+use nalgebra::Vector3;
+use rand::{Rng, RngCore};
+
+/**
+ * Indexed triangle mesh, sharing vertices between faces.
+ *
+ * Normals and UVs are per vertex and interpolated within faces. Faces are wound like `Triangle`, and vertex normals
+ * should point to the same side as the face normals.
+ */
+pub struct Mesh {
+    positions: Vec<Point>,
+    normals: Option<Vec<Dir>>,
+    uvs: Option<Vec<UV>>,
+    faces: Vec<[u32; 3]>,
+    bvh: Bvh<u32>,
+
+    // Cumulative face areas, for sampling as an area light
+    area_cdf: Vec<f64>,
+}
+
+impl Mesh {
+    /**
+     * Degenerated faces are dropped. Indices must be within `positions`, and `normals` and `uvs` if present should
+     * be as long as `positions`
+     */
+    pub fn new(positions: Vec<Point>, faces: Vec<[u32; 3]>, normals: Option<Vec<Dir>>, uvs: Option<Vec<UV>>) -> Self {
+        let faces: Vec<[u32; 3]> = faces.into_iter().filter(|face| {
+            let [a, b, c] = face.map(|i| positions[i as usize]);
+            (b - a).cross(&(c - a)).norm() >= EPS * EPS
+        }).collect();
+
+        let normals = normals.map(|normals| normals.into_iter().map(|n| if n.norm() < EPS { n } else { n.normalize() }).collect());
+
+        let bvh = Bvh::new(faces.iter().enumerate().map(|(idx, face)| {
+            let bb = face.iter().fold(BoundingBox::empty(), |acc, i| acc.merge(&BoundingBox::point(&positions[*i as usize])));
+            (idx as u32, bb)
+        }).collect());
+
+        let area_cdf = faces.iter().scan(0f64, |acc, face| {
+            let [a, b, c] = face.map(|i| positions[i as usize]);
+            *acc += (b - a).cross(&(c - a)).norm() / 2f64;
+            Some(*acc)
+        }).collect();
+
+        Self { positions, normals, uvs, faces, bvh, area_cdf }
+    }
+
+    /**
+     * Vertex normals averaged from the adjacent faces, weighted by their areas
+     */
+    pub fn smooth_normals(positions: &[Point], faces: &[[u32; 3]]) -> Vec<Dir> {
+        let mut normals = vec![Dir::zeros(); positions.len()];
+        for face in faces {
+            let [a, b, c] = face.map(|i| positions[i as usize]);
+            let weighted = (b - a).cross(&(c - a));
+            for i in face {
+                normals[*i as usize] += weighted;
+            }
+        }
+        normals
+    }
+
+    /**
+     * Number of faces, excluding degenerated ones
+     */
+    pub fn len(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faces.is_empty()
+    }
+
+    fn vertices(&self, face: usize) -> [&Point; 3] {
+        let [a, b, c] = self.faces[face];
+        [&self.positions[a as usize], &self.positions[b as usize], &self.positions[c as usize]]
+    }
+
+    fn face_normal(&self, face: usize) -> Dir {
+        let [a, b, c] = self.vertices(face);
+        (b - a).cross(&(c - a)).normalize()
+    }
+}
+
+impl super::Geometry for Mesh {
+    fn intersect(&self, ray: &Ray, upper: Option<f64>) -> Option<super::GeometryIntersect> {
+        let (face, t, beta, gamma) = self.bvh.intersect(
+            ray,
+            upper,
+            |face, upper| intersect_barycentric(self.vertices(*face as usize), ray, upper).map(|(t, b, g)| (*face as usize, t, b, g)),
+            |hit| hit.1,
+        )?;
+
+        // Only the closest hit is shaded
+        let [a, b, c] = self.faces[face].map(|i| i as usize);
+        let weights = [1f64 - beta - gamma, beta, gamma];
+        let interpolate = |values: [Vector3<f64>; 3]| values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2];
+
+        let face_normal = self.face_normal(face);
+        let norm = match &self.normals {
+            Some(normals) => {
+                let interpolated = interpolate([normals[a], normals[b], normals[c]]);
+                if interpolated.norm() < EPS { face_normal } else { interpolated.normalize() }
+            }
+            None => face_normal,
+        };
+
+        let vertices = self.vertices(face);
+        let barycentric = (vertices[1] - vertices[0], vertices[2] - vertices[0]);
+        let (uv, (dpdu, dpdv)) = match &self.uvs {
+            Some(uvs) => (
+                uvs[a] * weights[0] + uvs[b] * weights[1] + uvs[c] * weights[2],
+                uv_derivatives(vertices, [&uvs[a], &uvs[b], &uvs[c]]).unwrap_or(barycentric),
+            ),
+            None => (UV::new(beta, gamma), barycentric),
+        };
+
+        Some(super::GeometryIntersect {
+            dist: t,
+            norm,
+            uv,
+            // On the side of the interpolated normal, which may be flipped against the winding
+            geo_norm: if face_normal.dot(&norm) < 0f64 { -face_normal } else { face_normal },
+            dpdu,
+            dpdv,
+        })
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        self.bvh.bounding_box()
+    }
+}
+
+impl super::Surface for Mesh {
+    fn area(&self) -> f64 {
+        self.area_cdf.last().cloned().unwrap_or(0f64)
+    }
+
+    fn sample(&self, rng: &mut dyn RngCore) -> (Point, Dir) {
+        let target = rng.gen::<f64>() * self.area();
+        let face = self.area_cdf.partition_point(|c| *c <= target).min(self.faces.len() - 1);
+
+        // Folding the unit square keeps the distribution uniform
+        let (mut beta, mut gamma): (f64, f64) = (rng.gen(), rng.gen());
+        if beta + gamma > 1f64 {
+            beta = 1f64 - beta;
+            gamma = 1f64 - gamma;
+        }
+
+        let [a, b, c] = self.vertices(face);
+        (a * (1f64 - beta - gamma) + b * beta + c * gamma, self.face_normal(face))
+    }
+}
@@ -1,34 +1,103 @@
 pub mod triangle;
 pub mod sphere;
+pub mod mesh;
 pub mod util;
 
 use crate::material::Material;
+use crate::medium::InteriorMedium;
 use crate::renderer::*;
+use rand::RngCore;
+use std::sync::Arc;
+
+// Minimal cosine between the ray and adapted shading normals
+const SHADING_NORMAL_MARGIN: f64 = 1e-2;
 
 #[derive(Clone, Debug)]
 pub struct GeometryIntersect {
+    /// Shading normal
     pub norm: Dir,
     pub dist: f64,
+    pub uv: UV,
+    /// Normal of the actual surface, on the same side as `norm`
+    pub geo_norm: Dir,
+    /// Derivatives of the position over the texture coordinates
+    pub dpdu: Dir,
+    pub dpdv: Dir,
 }
 
 impl GeometryIntersect {
-    fn convert<'a>(self, material: &'a dyn Material) -> super::Intersect<'a> {
+    fn convert<'a>(self, material: &'a dyn Material, medium: Option<&'a InteriorMedium>) -> super::Intersect<'a> {
         super::Intersect {
             norm: self.norm,
             dist: self.dist,
+            uv: self.uv,
             material,
+            medium,
         }
     }
 }
 
+/**
+ * Bends the shading normal if `dir` is on the other side of it than of the geometric normal, which would let light
+ * leak through the surface, e.g. refracting into an object while leaving it
+ */
+fn adapt_shading_normal(dir: &Dir, geo_norm: &Dir, norm: &Dir) -> Dir {
+    let side = dir.dot(geo_norm);
+    if side * dir.dot(norm) > 0f64 {
+        return *norm;
+    }
+
+    // Just enough to be on the geometric side, keeping the rest of the deviation
+    let bent = norm - dir * (dir.dot(norm) - side.signum() * SHADING_NORMAL_MARGIN);
+    if bent.dot(geo_norm) > 0f64 {
+        bent.normalize()
+    } else {
+        *geo_norm
+    }
+}
+
 pub trait Geometry : Sync + Send {
     fn intersect(&self, ray: &crate::renderer::Ray, upper: Option<f64>) -> Option<GeometryIntersect>;
     fn bounding_box(&self) -> super::BoundingBox;
 }
 
+/**
+ * Geometry that can be sampled uniformly by area, e.g. for area lights
+ */
+pub trait Surface : Geometry {
+    fn area(&self) -> f64;
+
+    /**
+     * Uniformly samples a point on the surface, returning it with the normal there
+     */
+    fn sample(&self, rng: &mut dyn RngCore) -> (Point, Dir);
+}
+
+/**
+ * Shared geometry, e.g. a mesh that's also the surface of an area light
+ */
+impl<G> Geometry for Arc<G> where G: Geometry + ?Sized {
+    fn intersect(&self, ray: &crate::renderer::Ray, upper: Option<f64>) -> Option<GeometryIntersect> {
+        self.as_ref().intersect(ray, upper)
+    }
+    fn bounding_box(&self) -> super::BoundingBox {
+        self.as_ref().bounding_box()
+    }
+}
+
+impl<S> Surface for Arc<S> where S: Surface + ?Sized {
+    fn area(&self) -> f64 {
+        self.as_ref().area()
+    }
+    fn sample(&self, rng: &mut dyn RngCore) -> (Point, Dir) {
+        self.as_ref().sample(rng)
+    }
+}
+
 pub struct GeometryObject<G: Geometry, M: Material> {
     geometry: G,
     material: M,
+    medium: Option<InteriorMedium>,
 }
 
 impl<G, M> GeometryObject<G, M> where G: Geometry, M: Material {
@@ -36,13 +105,33 @@ impl<G, M> GeometryObject<G, M> where G: Geometry, M: Material {
         Self {
             geometry: g,
             material: m,
+            medium: None,
+        }
+    }
+
+    /**
+     * Fills the object with `medium`, which should be closed. Otherwise the interior absorbs as its material does
+     */
+    pub fn with_medium(self, medium: InteriorMedium) -> Self {
+        Self {
+            medium: Some(medium),
+            ..self
         }
     }
 }
 
 impl<G, M> super::Object for GeometryObject<G, M> where G: Geometry, M: Material {
-    fn intersect(&self, ray: &crate::renderer::Ray, upper: Option<f64>) -> Option<super::Intersect> {
-        self.geometry.intersect(ray, upper).map(|gi| gi.convert(&self.material))
+    /**
+     * Applies the normal map of the material, if any
+     */
+    fn intersect(&self, ray: &crate::renderer::Ray, upper: Option<f64>) -> Option<super::Intersect<'_>> {
+        self.geometry.intersect(ray, upper).map(|mut gi| {
+            if let Some(map) = self.material.normal_map() {
+                gi.norm = map.perturb(&gi.norm, &gi.dpdu, &gi.dpdv, &gi.uv);
+            }
+            gi.norm = adapt_shading_normal(&ray.dir, &gi.geo_norm, &gi.norm);
+            gi.convert(&self.material, self.medium.as_ref())
+        })
     }
     fn bounding_box(&self) -> super::BoundingBox {
         self.geometry.bounding_box()
@@ -50,39 +139,26 @@ impl<G, M> super::Object for GeometryObject<G, M> where G: Geometry, M: Material
 }
 
 pub struct GeometryGroup<G: Geometry> {
-    content: Vec<(G, super::BoundingBox)>,
+    content: super::bvh::Bvh<G>,
 }
 
 impl<G> From<Vec<G>> for GeometryGroup<G> where G: Geometry {
     fn from(input: Vec<G>) -> Self {
         Self {
-            content: input.into_iter().map(|g| {
+            content: super::bvh::Bvh::new(input.into_iter().map(|g| {
                 let bb = g.bounding_box();
                 (g, bb)
-            }).collect()
+            }).collect())
         }
     }
 }
 
 impl<G> Geometry for GeometryGroup<G> where G: Geometry {
-    fn intersect(&self, ray: &Ray, mut upper: Option<f64>) -> Option<GeometryIntersect> {
-        let mut result = None;
-        for (obj, bb) in self.content.iter() {
-            if !bb.hit(ray, upper) {
-                continue
-            }
-
-            let hit = obj.intersect(ray, upper);
-
-            if let Some(int) = hit {
-                upper = Some(int.dist);
-                result = Some(int);
-            }
-        }
-        result
+    fn intersect(&self, ray: &Ray, upper: Option<f64>) -> Option<GeometryIntersect> {
+        self.content.intersect(ray, upper, |g, upper| g.intersect(ray, upper), |int| int.dist)
     }
 
     fn bounding_box(&self) -> super::BoundingBox {
-        self.content.iter().fold(super::BoundingBox::unbounded(), |acc, (_, bb)| acc.merge(bb))
+        self.content.bounding_box()
     }
 }
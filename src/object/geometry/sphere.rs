@@ -1,6 +1,9 @@
 use nalgebra::Vector3;
 use crate::consts::*;
+use crate::renderer::*;
+use rand::{Rng, RngCore};
 
+#[derive(Clone)]
 pub struct Sphere {
     radius: f64,
     center: Vector3<f64>,
@@ -38,10 +41,29 @@ impl super::Geometry for Sphere {
         }
 
         let hitpoint = ray.interpolate(len);
+        let norm = (self.center - hitpoint).normalize(); // Reverted
+
+        // Longitude and latitude, with v = 1 at +Y
+        let u = (-norm[2]).atan2(-norm[0]).rem_euclid(2f64 * std::f64::consts::PI) / (2f64 * std::f64::consts::PI);
+        let v = 1f64 - (-norm[1]).clamp(-1f64, 1f64).acos() / std::f64::consts::PI;
+
+        // Outwards, derivatives of (sin(theta) cos(phi), cos(theta), sin(theta) sin(phi)) * radius
+        let (ox, oy, oz) = (-norm[0], -norm[1], -norm[2]);
+        let sin_theta = (ox * ox + oz * oz).sqrt();
+        let dpdu = Dir::new(-oz, 0f64, ox) * (2f64 * std::f64::consts::PI * self.radius);
+        let dpdv = if sin_theta < EPS {
+            Dir::new(1f64, 0f64, 0f64) * (std::f64::consts::PI * self.radius)
+        } else {
+            Dir::new(ox * oy / sin_theta, -sin_theta, oz * oy / sin_theta) * (-std::f64::consts::PI * self.radius)
+        };
 
         return Some(super::GeometryIntersect {
-            norm: (self.center - hitpoint).normalize(), // Reverted
+            norm,
             dist: len,
+            uv: UV::new(u, v),
+            geo_norm: norm,
+            dpdu,
+            dpdv,
         });
     }
 
@@ -53,3 +75,15 @@ impl super::Geometry for Sphere {
         }
     }
 }
+
+impl super::Surface for Sphere {
+    fn area(&self) -> f64 {
+        4f64 * std::f64::consts::PI * self.radius * self.radius
+    }
+
+    fn sample(&self, rng: &mut dyn RngCore) -> (Point, Dir) {
+        let [x, y, z]: [f64; 3] = rng.sample(rand_distr::UnitSphere);
+        let outwards = Dir::new(x, y, z);
+        (self.center + outwards * self.radius, -outwards)
+    }
+}
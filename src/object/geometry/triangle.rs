@@ -1,9 +1,68 @@
 use nalgebra::*;
 use crate::consts::EPS;
+use rand::{Rng, RngCore};
 
+/**
+ * Intersects the triangle `vertices`, returning the distance and the barycentric coordinates (beta, gamma)
+ * of vertices 1 and 2
+ */
+pub fn intersect_barycentric(vertices: [&Vector3<f64>; 3], ray: &crate::renderer::Ray, upper: Option<f64>) -> Option<(f64, f64, f64)> {
+    let e1 = vertices[0] - vertices[1];
+    let e2 = vertices[0] - vertices[2];
+    let s = vertices[0] - ray.origin;
+
+    if s.norm() < EPS { return None };
+
+    let d = Matrix3::from_columns(&[ray.dir, e1, e2]).determinant();
+    if d.abs() < EPS {
+        return None;
+    }
+
+    let t = Matrix3::from_columns(&[s, e1, e2]).determinant() / d;
+    let beta = Matrix3::from_columns(&[ray.dir, s, e2]).determinant() / d;
+    let gamma= Matrix3::from_columns(&[ray.dir, e1, s]).determinant() / d;
+
+    if beta < 0f64 || gamma < 0f64 || beta + gamma > 1f64{
+        return None;
+    }
+
+    if t < EPS {
+        return None;
+    }
+
+    if let Some(upper) = upper {
+        if upper < t {
+            return None;
+        }
+    }
+
+    Some((t, beta, gamma))
+}
+
+/**
+ * Derivatives of the position over the texture coordinates, or None if the UVs are degenerated
+ */
+pub fn uv_derivatives(vertices: [&Vector3<f64>; 3], uvs: [&crate::renderer::UV; 3]) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let leg_a = vertices[1] - vertices[0];
+    let leg_b = vertices[2] - vertices[0];
+    let (duv_a, duv_b) = (uvs[1] - uvs[0], uvs[2] - uvs[0]);
+
+    // Solving leg = dpdu * du + dpdv * dv for both legs
+    let det = duv_a[0] * duv_b[1] - duv_a[1] * duv_b[0];
+    if det.abs() < EPS * EPS {
+        return None;
+    }
+    Some(((leg_a * duv_b[1] - leg_b * duv_a[1]) / det, (leg_b * duv_a[0] - leg_a * duv_b[0]) / det))
+}
+
+#[derive(Clone)]
 pub struct Triangle {
     vertices: [Vector3<f64>; 3],
     normal: Vector3<f64>,
+    /// Barycentric coordinates are used if absent
+    uvs: Option<[crate::renderer::UV; 3]>,
+    dpdu: Vector3<f64>,
+    dpdv: Vector3<f64>,
 }
 
 impl Triangle {
@@ -16,64 +75,71 @@ impl Triangle {
         Triangle {
             vertices,
             normal,
+            uvs: None,
+            dpdu: leg_a,
+            dpdv: leg_b,
         }
     }
-}
-
-impl super::Geometry for Triangle {
-    fn intersect(&self, ray: &crate::renderer::Ray, upper: Option<f64>) -> Option<super::GeometryIntersect> {
-        let e1 = self.vertices[0] - self.vertices[1];
-        let e2 = self.vertices[0] - self.vertices[2];
-        let s = self.vertices[0] - ray.origin;
-
-        if s.norm() < EPS { return None };
 
-        let d = Matrix3::from_columns(&[ray.dir, e1, e2]).determinant();
-        if d.abs() < EPS {
-            return None;
-        }
+    pub fn vertices(&self) -> &[Vector3<f64>; 3] {
+        &self.vertices
+    }
 
-        let t = Matrix3::from_columns(&[s, e1, e2]).determinant() / d;
-        let beta = Matrix3::from_columns(&[ray.dir, s, e2]).determinant() / d;
-        let gamma= Matrix3::from_columns(&[ray.dir, e1, s]).determinant() / d;
+    /**
+     * Sets the texture coordinates of the vertices
+     */
+    pub fn with_uvs(self, uvs: [crate::renderer::UV; 3]) -> Self {
+        // Keeping the barycentric ones if the UVs are degenerated
+        let v = &self.vertices;
+        let (dpdu, dpdv) = uv_derivatives([&v[0], &v[1], &v[2]], [&uvs[0], &uvs[1], &uvs[2]]).unwrap_or((self.dpdu, self.dpdv));
 
-        if beta < 0f64 || gamma < 0f64 || beta + gamma > 1f64{
-            return None;
+        Triangle {
+            uvs: Some(uvs),
+            dpdu,
+            dpdv,
+            ..self
         }
+    }
+}
 
-        if t < EPS {
-            return None;
-        }
+impl super::Geometry for Triangle {
+    fn intersect(&self, ray: &crate::renderer::Ray, upper: Option<f64>) -> Option<super::GeometryIntersect> {
+        let v = &self.vertices;
+        let (t, beta, gamma) = intersect_barycentric([&v[0], &v[1], &v[2]], ray, upper)?;
 
-        if let Some(upper) = upper {
-            if upper < t {
-                return None;
-            }
-        }
+        let uv = if let Some(uvs) = &self.uvs {
+            uvs[0] * (1f64 - beta - gamma) + uvs[1] * beta + uvs[2] * gamma
+        } else {
+            crate::renderer::UV::new(beta, gamma)
+        };
 
         Some(
             super::GeometryIntersect {
                 dist: t,
                 norm: self.normal.clone(),
+                uv,
+                geo_norm: self.normal,
+                dpdu: self.dpdu,
+                dpdv: self.dpdv,
             }
         )
     }
 
     fn bounding_box(&self) -> crate::object::BoundingBox {
         crate::object::BoundingBox {
-            x: self.vertices.iter().fold((std::f64::NEG_INFINITY, std::f64::INFINITY), |(lower, upper), elem| {
+            x: self.vertices.iter().fold((std::f64::INFINITY, std::f64::NEG_INFINITY), |(lower, upper), elem| {
                 (
                     lower.min(elem[0]),
                     upper.max(elem[0]),
                 )
             }),
-            y: self.vertices.iter().fold((std::f64::NEG_INFINITY, std::f64::INFINITY), |(lower, upper), elem| {
+            y: self.vertices.iter().fold((std::f64::INFINITY, std::f64::NEG_INFINITY), |(lower, upper), elem| {
                 (
                     lower.min(elem[1]),
                     upper.max(elem[1]),
                 )
             }),
-            z: self.vertices.iter().fold((std::f64::NEG_INFINITY, std::f64::INFINITY), |(lower, upper), elem| {
+            z: self.vertices.iter().fold((std::f64::INFINITY, std::f64::NEG_INFINITY), |(lower, upper), elem| {
                 (
                     lower.min(elem[2]),
                     upper.max(elem[2]),
@@ -82,3 +148,21 @@ impl super::Geometry for Triangle {
         }
     }
 }
+
+impl super::Surface for Triangle {
+    fn area(&self) -> f64 {
+        (self.vertices[1] - self.vertices[0]).cross(&(self.vertices[2] - self.vertices[0])).norm() / 2f64
+    }
+
+    fn sample(&self, rng: &mut dyn RngCore) -> (crate::renderer::Point, crate::renderer::Dir) {
+        // Folding the unit square keeps the distribution uniform
+        let (mut beta, mut gamma): (f64, f64) = (rng.gen(), rng.gen());
+        if beta + gamma > 1f64 {
+            beta = 1f64 - beta;
+            gamma = 1f64 - gamma;
+        }
+
+        let at = self.vertices[0] * (1f64 - beta - gamma) + self.vertices[1] * beta + self.vertices[2] * gamma;
+        (at, self.normal)
+    }
+}
@@ -88,7 +88,7 @@ pub fn create_box(lower: Vector3<f64>, upper: Vector3<f64>) -> Vec<Triangle> {
         Vector3::new(lower[0], lower[1], upper[2]),
     ]);
 
-    vec![
+    let triangles = vec![
         upper_a,
         upper_b,
         lower_a,
@@ -101,5 +101,16 @@ pub fn create_box(lower: Vector3<f64>, upper: Vector3<f64>) -> Vec<Triangle> {
         left_b,
         right_a,
         right_b,
-    ]
+    ];
+
+    // Every face covers the whole texture, spanned by the two axes it's parallel to
+    let extent = upper - lower;
+    triangles.into_iter().map(|t| {
+        let normal = (t.vertices()[1] - t.vertices()[0]).cross(&(t.vertices()[2] - t.vertices()[0]));
+        let axis = normal.iamax();
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let uv = |p: &Vector3<f64>| Vector2::new((p[a] - lower[a]) / extent[a], (p[b] - lower[b]) / extent[b]);
+        let uvs = [uv(&t.vertices()[0]), uv(&t.vertices()[1]), uv(&t.vertices()[2])];
+        t.with_uvs(uvs)
+    }).collect()
 }
@@ -1,9 +1,10 @@
 pub mod geometry;
+pub mod bvh;
 
 use super::renderer::*;
 use crate::consts::*;
 use super::material::Material;
-use std::sync::Arc;
+use super::medium::InteriorMedium;
 
 #[derive(Copy, Clone)]
 #[allow(dead_code)]
@@ -14,15 +15,23 @@ pub struct BoundingBox {
 }
 
 impl BoundingBox {
-    fn unbounded() -> Self {
+    pub fn empty() -> Self {
         Self {
-            x: (std::f64::NEG_INFINITY, std::f64::INFINITY),
-            y: (std::f64::NEG_INFINITY, std::f64::INFINITY),
-            z: (std::f64::NEG_INFINITY, std::f64::INFINITY),
+            x: (f64::INFINITY, f64::NEG_INFINITY),
+            y: (f64::INFINITY, f64::NEG_INFINITY),
+            z: (f64::INFINITY, f64::NEG_INFINITY),
         }
     }
 
-    fn merge(&self, another: &BoundingBox) -> BoundingBox {
+    pub fn point(p: &Point) -> Self {
+        Self {
+            x: (p[0], p[0]),
+            y: (p[1], p[1]),
+            z: (p[2], p[2]),
+        }
+    }
+
+    pub fn merge(&self, another: &BoundingBox) -> BoundingBox {
         BoundingBox {
             x: (self.x.0.min(another.x.0), self.x.1.max(another.x.1)),
             y: (self.y.0.min(another.y.0), self.y.1.max(another.y.1)),
@@ -30,40 +39,74 @@ impl BoundingBox {
         }
     }
 
-    fn hit(&self, ray: &Ray, upper: Option<f64>) -> bool {
-        let bound = if let Some(u) = upper { u } else { std::f64::INFINITY };
-
-        let tx1 = if ray.dir[0].abs() < EPS { std::f64::INFINITY } else { (self.x.0 - ray.origin[0]) * ray.invdir[0] };
-        let tx2 = if ray.dir[0].abs() < EPS { std::f64::NEG_INFINITY } else { (self.x.1 - ray.origin[0]) * ray.invdir[0] };
+    pub fn axis(&self, axis: usize) -> (f64, f64) {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
 
-        let mut tmin = tx1.min(tx2);
-        let mut tmax = tx1.max(tx2).min(bound);
+    pub fn extent(&self) -> Dir {
+        Dir::new(self.x.1 - self.x.0, self.y.1 - self.y.0, self.z.1 - self.z.0).map(|e| e.max(0f64))
+    }
 
-        if tmin > bound { return false; }
+    /**
+     * Center of the box. Unbounded axes are centered at the finite side, or at 0
+     */
+    pub fn centroid(&self) -> Point {
+        let center = |(lower, upper): (f64, f64)| match (lower.is_finite(), upper.is_finite()) {
+            (true, true) => (lower + upper) / 2f64,
+            (true, false) => lower,
+            (false, true) => upper,
+            (false, false) => 0f64,
+        };
+        Point::new(center(self.x), center(self.y), center(self.z))
+    }
 
-        if ray.dir[1].abs() > EPS {
-            let ty1 = (self.y.0 - ray.origin[1]) * ray.invdir[1];
-            let ty2 = (self.y.1 - ray.origin[1]) * ray.invdir[1];
+    pub fn surface_area(&self) -> f64 {
+        // Clamped so that unbounded boxes don't produce NaNs in SAH
+        let e = self.extent().map(|e| e.min(1e100));
+        2f64 * (e[0] * e[1] + e[1] * e[2] + e[2] * e[0])
+    }
 
-            let tymin = ty1.min(ty2);
-            let tymax = ty1.max(ty2);
+    /**
+     * Returns the distance at which the ray enters the box, if it hits the box before `upper`
+     */
+    pub fn hit(&self, ray: &Ray, upper: Option<f64>) -> Option<f64> {
+        self.clip(ray, upper).map(|(tmin, _)| tmin)
+    }
 
-            if tmin > tymax || tmax < tymin { return false; }
-            tmin = tmin.max(tymin);
-            tmax = tmax.min(tymax);
-        }
+    /**
+     * Returns the distances at which the ray enters and leaves the box, within [0, `upper`]
+     */
+    pub fn clip(&self, ray: &Ray, upper: Option<f64>) -> Option<(f64, f64)> {
+        let mut tmin = 0f64;
+        let mut tmax = upper.unwrap_or(f64::INFINITY);
+
+        for axis in 0..3 {
+            let (lower, upper) = self.axis(axis);
+            let (lower, upper) = (lower - EPS, upper + EPS);
+
+            if ray.dir[axis] == 0f64 {
+                if ray.origin[axis] < lower || ray.origin[axis] > upper {
+                    return None;
+                }
+                continue;
+            }
 
-        if ray.dir[2].abs() > EPS {
-            let tz1 = (self.z.0 - ray.origin[2]) * ray.invdir[2];
-            let tz2 = (self.z.1 - ray.origin[2]) * ray.invdir[2];
+            let t1 = (lower - ray.origin[axis]) * ray.invdir[axis];
+            let t2 = (upper - ray.origin[axis]) * ray.invdir[axis];
 
-            let tzmin = tz1.min(tz2);
-            let tzmax = tz1.max(tz2);
+            tmin = tmin.max(t1.min(t2));
+            tmax = tmax.min(t1.max(t2));
 
-            if tmin > tzmax || tmax < tzmin { return false; }
+            if tmin > tmax {
+                return None;
+            }
         }
 
-        true
+        Some((tmin, tmax))
     }
 }
 
@@ -71,7 +114,10 @@ impl BoundingBox {
 pub struct Intersect<'a> {
     pub norm: Dir,
     pub dist: f64,
+    pub uv: UV,
     pub material: &'a dyn Material,
+    /// Filling the object, if declared
+    pub medium: Option<&'a InteriorMedium>,
 }
 
 pub trait Object: Sync + Send {
@@ -89,39 +135,26 @@ impl<T> Object for T where T: AsRef<dyn Object> + Send + Sync {
 }
 
 pub struct ObjectGroup<O: Object> {
-    content: Vec<(O, BoundingBox)>,
+    content: bvh::Bvh<O>,
 }
 
 impl<O> From<Vec<O>> for ObjectGroup<O> where O: Object {
     fn from(input: Vec<O>) -> Self {
         Self {
-            content: input.into_iter().map(|g| {
+            content: bvh::Bvh::new(input.into_iter().map(|g| {
                 let bb = g.bounding_box();
                 (g, bb)
-            }).collect()
+            }).collect())
         }
     }
 }
 
 impl<O> Object for ObjectGroup<O> where O: Object {
-    fn intersect(&self, ray: &Ray, mut upper: Option<f64>) -> Option<Intersect> {
-        let mut result = None;
-        for (obj, bb) in self.content.iter() {
-            if !bb.hit(ray, upper) {
-                continue
-            }
-
-            let hit = obj.intersect(ray, upper);
-
-            if let Some(int) = hit {
-                upper = Some(int.dist);
-                result = Some(int);
-            }
-        }
-        result
+    fn intersect(&self, ray: &Ray, upper: Option<f64>) -> Option<Intersect> {
+        self.content.intersect(ray, upper, |obj, upper| obj.intersect(ray, upper), |int| int.dist)
     }
 
     fn bounding_box(&self) -> BoundingBox {
-        self.content.iter().fold(BoundingBox::unbounded(), |acc, (_, bb)| acc.merge(bb))
+        self.content.bounding_box()
     }
 }
@@ -0,0 +1,140 @@
+use crate::renderer::*;
+use crate::tonemap::ToneMapping;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// 8-bit sRGB, tone mapped
+    Png,
+    /// Portable float map, linear
+    Pfm,
+    /// Radiance RGBE, linear
+    Hdr,
+    /// OpenEXR with 32-bit float channels, linear
+    Exr,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Pfm => "pfm",
+            OutputFormat::Hdr => "hdr",
+            OutputFormat::Exr => "exr",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(OutputFormat::Png),
+            "pfm" => Ok(OutputFormat::Pfm),
+            "hdr" => Ok(OutputFormat::Hdr),
+            "exr" => Ok(OutputFormat::Exr),
+            _ => Err(format!("unknown output format `{}`, expected one of png, pfm, hdr, exr", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/**
+ * Writes the averaged buffer as `<stem>.<ext>` for every given format.
+ * Tone mapping only applies to low dynamic range formats.
+ */
+pub fn write_all(
+    buffer: &[Vec<Color>],
+    rounds: usize,
+    formats: &[OutputFormat],
+    tone_mapping: &ToneMapping,
+    stem: &str,
+) -> Result<(), String> {
+    for format in formats {
+        let path = format!("{}.{}", stem, format.extension());
+        write(buffer, rounds, *format, tone_mapping, path.as_ref()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+pub fn write(
+    buffer: &[Vec<Color>],
+    rounds: usize,
+    format: OutputFormat,
+    tone_mapping: &ToneMapping,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        OutputFormat::Png => result_to_image(buffer, rounds, tone_mapping).save(path)?,
+        OutputFormat::Pfm => write_pfm(buffer, rounds, path)?,
+        OutputFormat::Hdr => write_hdr(buffer, rounds, path)?,
+        OutputFormat::Exr => write_exr(buffer, rounds, path)?,
+    }
+    Ok(())
+}
+
+fn radiance(buffer: &[Vec<Color>], rounds: usize, x: usize, y: usize) -> [f32; 3] {
+    let c = buffer[x][y] / rounds as f64;
+    [c[0] as f32, c[1] as f32, c[2] as f32]
+}
+
+pub fn result_to_image(buffer: &[Vec<Color>], rounds: usize, tone_mapping: &ToneMapping) -> image::RgbImage {
+    let mut img = image::RgbImage::new(buffer.len() as u32, buffer[0].len() as u32);
+    for (x, col) in buffer.iter().enumerate() {
+        for (y, elem) in col.iter().enumerate() {
+            let color = tone_mapping.encode_srgb8(&(elem / rounds as f64));
+            img.put_pixel(x as u32, y as u32, image::Rgb(color));
+        }
+    }
+    img
+}
+
+/**
+ * PFM stores scanlines from bottom to top, negative scale means little endian
+ */
+fn write_pfm(buffer: &[Vec<Color>], rounds: usize, path: &Path) -> std::io::Result<()> {
+    let width = buffer.len();
+    let height = buffer[0].len();
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for c in radiance(buffer, rounds, x, y).iter() {
+                file.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+fn write_hdr(buffer: &[Vec<Color>], rounds: usize, path: &Path) -> image::ImageResult<()> {
+    let width = buffer.len();
+    let height = buffer[0].len();
+
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            data.push(image::Rgb(radiance(buffer, rounds, x, y)));
+        }
+    }
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    image::hdr::HDREncoder::new(file).encode(&data, width, height)
+}
+
+fn write_exr(buffer: &[Vec<Color>], rounds: usize, path: &Path) -> exr::error::UnitResult {
+    exr::prelude::write_rgb_file(path, buffer.len(), buffer[0].len(), |x, y| {
+        let [r, g, b] = radiance(buffer, rounds, x, y);
+        (r, g, b)
+    })
+}
@@ -0,0 +1,196 @@
+use crate::light::tangents;
+use crate::renderer::*;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+
+/**
+ * Angular distribution of light scattered in a medium, depending only on the angle between the directions before and
+ * after scattering
+ */
+pub trait PhaseFunction: Send + Sync {
+    /// Density over the sphere of directions, of scattering by the angle whose cosine is `cos`
+    fn eval(&self, cos: f64) -> f64;
+
+    /// Cosine of the scattering angle, distributed by `eval`
+    fn sample_cos(&self, rng: &mut dyn RngCore) -> f64;
+
+    /**
+     * Samples the direction after scattering a ray traveling along `dir`
+     */
+    fn sample(&self, dir: &Dir, rng: &mut dyn RngCore) -> Dir {
+        let dir = dir.normalize();
+        let (tangent, bitangent) = tangents(&dir);
+
+        let cos = self.sample_cos(rng).clamp(-1f64, 1f64);
+        let sin = (1f64 - cos * cos).max(0f64).sqrt();
+        let phi = rng.gen_range(0f64, PI * 2f64);
+        (tangent * (sin * phi.cos()) + bitangent * (sin * phi.sin()) + dir * cos).normalize()
+    }
+}
+
+/**
+ * Scatters equally in all directions
+ */
+pub struct Isotropic;
+
+impl PhaseFunction for Isotropic {
+    fn eval(&self, _cos: f64) -> f64 {
+        1f64 / (4f64 * PI)
+    }
+
+    fn sample_cos(&self, rng: &mut dyn RngCore) -> f64 {
+        1f64 - 2f64 * rng.gen::<f64>()
+    }
+}
+
+/**
+ * Henyey-Greenstein phase function. `g` within (-1, 1) is the mean cosine, positive for forward scattering
+ */
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self { g }
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn eval(&self, cos: f64) -> f64 {
+        let g = self.g;
+        (1f64 - g * g) / (4f64 * PI * (1f64 + g * g - 2f64 * g * cos).powf(1.5f64))
+    }
+
+    fn sample_cos(&self, rng: &mut dyn RngCore) -> f64 {
+        let (g, u) = (self.g, rng.gen::<f64>());
+        if g.abs() < 1e-3 {
+            return 1f64 - 2f64 * u;
+        }
+        let s = (1f64 - g * g) / (1f64 - g + 2f64 * g * u);
+        (1f64 + g * g - s * s) / (2f64 * g)
+    }
+}
+
+/**
+ * Rayleigh scattering by particles much smaller than the wavelength, e.g. air molecules
+ */
+pub struct Rayleigh;
+
+impl PhaseFunction for Rayleigh {
+    fn eval(&self, cos: f64) -> f64 {
+        3f64 / (16f64 * PI) * (1f64 + cos * cos)
+    }
+
+    /**
+     * Inverts the CDF, solving `cos^3 + 3 cos = 2z` with Cardano's formula
+     */
+    fn sample_cos(&self, rng: &mut dyn RngCore) -> f64 {
+        let z = 4f64 * rng.gen::<f64>() - 2f64;
+        let root = (z * z + 1f64).sqrt();
+        (z + root).cbrt() + (z - root).cbrt()
+    }
+}
+
+/**
+ * Cornette-Shanks approximation of Mie scattering by particles comparable to the wavelength, e.g. haze and water
+ * droplets. `g` within (-1, 1) is the asymmetry, as in Henyey-Greenstein
+ */
+pub struct Mie {
+    hg: HenyeyGreenstein,
+}
+
+impl Mie {
+    pub fn new(g: f64) -> Self {
+        Self { hg: HenyeyGreenstein::new(g) }
+    }
+}
+
+impl PhaseFunction for Mie {
+    fn eval(&self, cos: f64) -> f64 {
+        let g = self.hg.g;
+        1.5f64 * (1f64 + cos * cos) / (2f64 + g * g) * self.hg.eval(cos)
+    }
+
+    /**
+     * Henyey-Greenstein samples, accepted with probability `(1 + cos^2) / 2`, proportional to the ratio of the two
+     */
+    fn sample_cos(&self, rng: &mut dyn RngCore) -> f64 {
+        loop {
+            let cos = self.hg.sample_cos(rng);
+            if rng.gen::<f64>() * 2f64 < 1f64 + cos * cos {
+                return cos;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phases() -> Vec<(&'static str, Box<dyn PhaseFunction>)> {
+        vec![
+            ("isotropic", Box::new(Isotropic)),
+            ("hg forward", Box::new(HenyeyGreenstein::new(0.9f64))),
+            ("hg backward", Box::new(HenyeyGreenstein::new(-0.4f64))),
+            ("hg nearly isotropic", Box::new(HenyeyGreenstein::new(1e-4f64))),
+            ("rayleigh", Box::new(Rayleigh)),
+            ("mie", Box::new(Mie::new(0.76f64))),
+        ]
+    }
+
+    /**
+     * Integral of the density over the directions whose cosine is within [lower, upper], by Simpson's rule
+     */
+    fn integrate(phase: &dyn PhaseFunction, lower: f64, upper: f64) -> f64 {
+        let steps = 2000;
+        let h = (upper - lower) / steps as f64;
+        let sum: f64 = (0..=steps).map(|i| {
+            let weight = if i == 0 || i == steps { 1f64 } else if i % 2 == 1 { 4f64 } else { 2f64 };
+            weight * phase.eval(lower + h * i as f64)
+        }).sum();
+        2f64 * PI * sum * h / 3f64
+    }
+
+    #[test]
+    fn densities_are_normalized() {
+        for (name, phase) in phases() {
+            let total = integrate(phase.as_ref(), -1f64, 1f64);
+            assert!((total - 1f64).abs() < 1e-4f64, "{} integrates to {}", name, total);
+        }
+    }
+
+    #[test]
+    fn samples_follow_the_density() {
+        const BINS: usize = 20;
+        const SAMPLES: usize = 200000;
+
+        let mut rng = crate::renderer::task_rng(3, 0, 0);
+        for (name, phase) in phases() {
+            let mut counts = [0usize; BINS];
+            let dir = Dir::new(0f64, 0f64, 1f64);
+            for _ in 0..SAMPLES {
+                let cos = phase.sample(&dir, &mut rng).dot(&dir);
+                counts[(((cos + 1f64) / 2f64 * BINS as f64) as usize).min(BINS - 1)] += 1;
+            }
+
+            for (bin, count) in counts.iter().enumerate() {
+                let lower = -1f64 + 2f64 * bin as f64 / BINS as f64;
+                let expected = integrate(phase.as_ref(), lower, lower + 2f64 / BINS as f64);
+                let found = *count as f64 / SAMPLES as f64;
+                assert!((found - expected).abs() < 5e-3f64, "{}: bin {} has {}, expected {}", name, bin, found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_has_the_mean_cosine_g() {
+        let mut rng = crate::renderer::task_rng(5, 0, 0);
+        for &g in &[-0.5f64, 0f64, 0.3f64, 0.9f64] {
+            let phase = HenyeyGreenstein::new(g);
+            let mean = (0..100000).map(|_| phase.sample_cos(&mut rng)).sum::<f64>() / 100000f64;
+            assert!((mean - g).abs() < 1e-2f64, "mean cosine {} for g = {}", mean, g);
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use super::checkpoint::Checkpoint;
+use super::consts::*;
+use super::light::*;
+use super::material::Material;
+use super::medium::{InteriorMedium, PhaseMixture};
+use super::phase::Isotropic;
+use super::object::Intersect;
+use super::scene::Scene;
+use super::spectrum::Wavelength;
+use log::*;
+use nalgebra::Vector3;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+pub mod path;
+pub mod ppm;
+pub mod sppm;
+
+pub type Point = Vector3<f64>;
+pub type Color = Vector3<f64>;
+pub type Dir = Vector3<f64>;
+/// Surface parameterization, for looking up textures
+pub type UV = nalgebra::Vector2<f64>;
+pub type RenderBuffer = Vec<Vec<Color>>;
+pub type RenderRng = rand_pcg::Pcg64;
+pub type PhotonMap = kdtree::KdTree<f64, Photon, [f64; 3]>;
+
+// Fixed PCG stream, iterations are distinguished by the initial state
+const RNG_STREAM: u128 = 0xa02b_dbf7_bb3c_0a7a_c28f_a16a_64ab_f96b;
+
+#[derive(Clone, Debug)]
+pub struct Ray {
+    pub origin: Point,
+    pub dir: Dir,
+    pub invdir: Vector3<f64>,
+}
+
+impl Ray {
+    pub fn new(origin: Point, dir: Dir) -> Ray {
+        let dir = dir.normalize();
+        Ray {
+            origin,
+            dir,
+            invdir: dir.map(|r| 1f64 / r),
+        }
+    }
+
+    pub fn interpolate(&self, dist: f64) -> Point {
+        self.origin + self.dir * dist
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    /// Probabilistic progressive photon mapping, with a global radius schedule
+    Ppm,
+    /// Stochastic progressive photon mapping, with per-pixel radius and photon statistics
+    Sppm,
+    /// Path tracing with next event estimation, for reference renders
+    Path,
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ppm" => Ok(Integrator::Ppm),
+            "sppm" => Ok(Integrator::Sppm),
+            "path" => Ok(Integrator::Path),
+            _ => Err(format!("unknown integrator `{}`, expected one of ppm, sppm, path", s)),
+        }
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Integrator::Ppm => "ppm",
+            Integrator::Sppm => "sppm",
+            Integrator::Path => "path",
+        })
+    }
+}
+
+/**
+ * Radius after `steps` + 1 updates from `radius_0`, following r_{i+1}^2 = r_i^2 (i + alpha) / (i + 1)
+ */
+pub fn radius_at(args: &super::Args, steps: usize) -> f64 {
+    (0..=steps).fold(args.radius_0, |radius, i| radius * ((i as f64 + args.alpha) / (i + 1) as f64).sqrt())
+}
+
+/**
+ * RNG for the given iteration, so the result doesn't depend on which worker runs it
+ */
+pub fn iteration_rng(seed: u64, iter: usize) -> RenderRng {
+    task_rng(seed, iter, 0)
+}
+
+/**
+ * RNG for an independent task within the given iteration, e.g. a chunk of photons
+ */
+pub fn task_rng(seed: u64, iter: usize, task: usize) -> RenderRng {
+    RenderRng::new(((seed as u128) << 64) | iter as u128, RNG_STREAM.wrapping_add(task as u128))
+}
+
+/**
+ * Renders the scene with the selected integrator, continuing from `resume` if given
+ */
+pub fn render(mut args: super::Args, scene: Scene, resume: Option<Checkpoint>) {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    // Recorded in the checkpoints
+    args.seed = Some(seed);
+    info!("Seed: {}, integrator: {}", seed, args.integrator);
+
+    let on_checkpoint = |pixels: &[Vec<Color>], done| save(&args, pixels, done);
+    match args.integrator {
+        Integrator::Ppm => ppm::render(&args, &scene, seed, resume, on_checkpoint),
+        Integrator::Sppm => sppm::render(&args, &scene, seed, on_checkpoint),
+        Integrator::Path => path::render(&args, &scene, seed, resume, on_checkpoint),
+    }
+}
+
+/**
+ * Emission seen along `dir` at a surface, scaled like diffuse surfaces: a white diffuse surface under
+ * irradiance E looks the same as an emitter with exitance E
+ */
+pub fn emitted(material: &dyn Material, dir: &Dir, norm: &Dir, wavelength: Wavelength) -> Color {
+    let cos = dir.dot(norm) / norm.norm();
+    if cos <= 0f64 {
+        return Color::zeros();
+    }
+    wavelength.radiance(&material.emission()) * cos
+}
+
+/**
+ * Fraction of light left after `dist` units through a medium with the absorption coefficient `absorption`
+ * (Beer-Lambert law)
+ */
+pub fn transmittance(absorption: &Color, dist: f64) -> Color {
+    absorption.map(|a| (-a * dist).exp())
+}
+
+/**
+ * Interiors of the objects a path has entered, innermost last. Outside all of them, it travels through the global fog
+ * and the bounded media
+ */
+#[derive(Clone, Default)]
+pub struct MediumStack<'a> {
+    interiors: Vec<(&'a dyn Material, Option<&'a InteriorMedium>)>,
+}
+
+impl<'a> MediumStack<'a> {
+    /**
+     * Updates the stack after a ray along `inc` is scattered into `out` at `int`. Rays crossing the surface enter the
+     * interior of the object, or leave it
+     */
+    pub fn cross(&mut self, int: &Intersect<'a>, inc: &Dir, out: &Dir) {
+        let entering = inc.dot(&int.norm) > 0f64;
+        if entering != (out.dot(&int.norm) > 0f64) {
+            return;
+        }
+
+        if entering {
+            self.interiors.push((int.material, int.medium));
+        } else if let Some(idx) = self.interiors.iter().rposition(|(material, medium)| {
+            std::ptr::addr_eq(*material, int.material) && medium.map(|m| m as *const _) == int.medium.map(|m| m as *const _)
+        }) {
+            self.interiors.remove(idx);
+        }
+    }
+
+    pub fn is_outside(&self) -> bool {
+        self.interiors.is_empty()
+    }
+
+    /**
+     * Absorption coefficient of the innermost interior, of its medium if declared or of its material otherwise
+     */
+    pub fn absorption(&self, wavelength: Wavelength) -> Color {
+        match self.interiors.last() {
+            None => Color::zeros(),
+            Some((_, Some(medium))) => wavelength.filter(&medium.absorption),
+            Some((material, None)) => wavelength.filter(&material.absorption()),
+        }
+    }
+
+    fn scattering(&self) -> f64 {
+        self.interiors.last().and_then(|(_, medium)| *medium).map_or(0f64, |m| m.scattering)
+    }
+
+    /**
+     * Media interacting at `at`, which is on the current segment. The global fog is isotropic and absorbs all photons
+     */
+    pub fn phase<'b>(&'b self, args: &super::Args, scene: &'b Scene, at: &Point) -> PhaseMixture<'b> {
+        let mut mixture = PhaseMixture::default();
+        match self.interiors.last() {
+            None => {
+                mixture.add(1f64 / args.mean_dist, &Isotropic, 0f64);
+                scene.media.mix_into(at, &mut mixture);
+            }
+            Some((_, Some(medium))) => mixture.add(medium.scattering, medium.phase.as_ref(), medium.albedo),
+            Some((_, None)) => {}
+        }
+        mixture
+    }
+
+    /**
+     * Distance to the first interaction along the ray before `dist`, in the bounded media or in the innermost interior.
+     * Those with the global fog are sampled separately
+     */
+    pub fn interaction(&self, scene: &Scene, ray: &Ray, dist: f64, rng: &mut RenderRng) -> Option<f64> {
+        if self.is_outside() {
+            return scene.media.interaction(ray, dist, rng);
+        }
+
+        let scattering = self.scattering();
+        if scattering <= 0f64 {
+            return None;
+        }
+        let t = -(1f64 - rng.gen::<f64>()).ln() / scattering;
+        if t < dist { Some(t) } else { None }
+    }
+
+    /**
+     * Fraction of photons passing the first `dist` units of the ray, without interacting or being absorbed
+     */
+    pub fn transmittance(&self, args: &super::Args, scene: &Scene, ray: &Ray, dist: f64, wavelength: Wavelength, rng: &mut RenderRng) -> Color {
+        if self.is_outside() {
+            let fraction = (-dist / args.mean_dist).exp() * scene.media.transmittance(ray, dist, rng);
+            return Color::repeat(fraction);
+        }
+        transmittance(&self.absorption(wavelength), dist) * (-self.scattering() * dist).exp()
+    }
+}
+
+/**
+ * Irradiance arriving at `at` directly from all lights, with shadow rays and attenuation by `media`, where `at` is.
+ * Each light is weighted by `weight` of its sample
+ */
+pub fn direct_light(
+    args: &super::Args,
+    scene: &Scene,
+    at: &Point,
+    media: &MediumStack,
+    wavelength: Wavelength,
+    rng: &mut RenderRng,
+    weight: impl Fn(&DirectSample) -> f64,
+) -> Color {
+    let mut irradiance: Color = Default::default();
+
+    for light in scene.lights.iter() {
+        let sample = match light.sample_direct(at, rng) {
+            Some(s) => s,
+            None => continue,
+        };
+
+        if scene.occluded(at, &sample.dir, sample.dist) {
+            continue;
+        }
+
+        // Photons interacting with the media leave the direct beam, whether absorbed or scattered
+        let transmittance = media.transmittance(args, scene, &Ray::new(*at, sample.dir), sample.dist, wavelength, rng);
+        irradiance += wavelength.light(light, &sample.irradiance).component_mul(&transmittance) * weight(&sample);
+    }
+
+    irradiance
+}
+
+/**
+ * Irradiance arriving directly from all lights on the surface at `at` with normal `norm`
+ */
+pub fn direct_irradiance(args: &super::Args, scene: &Scene, at: &Point, norm: &Dir, media: &MediumStack, wavelength: Wavelength, rng: &mut RenderRng) -> Color {
+    direct_light(args, scene, at, media, wavelength, rng, |sample| sample.dir.dot(norm).abs() / norm.norm())
+}
+
+/**
+ * Number of photons emitted from and recorded for each light
+ */
+#[derive(Clone, Debug)]
+pub struct PhotonStats {
+    pub emitted: Vec<usize>,
+    pub recorded: Vec<usize>,
+}
+
+impl PhotonStats {
+    pub fn new(lights: usize) -> Self {
+        Self {
+            emitted: vec![0; lights],
+            recorded: vec![0; lights],
+        }
+    }
+
+    pub fn merge(&mut self, other: &PhotonStats) {
+        for (a, b) in self.emitted.iter_mut().zip(&other.emitted) {
+            *a += b;
+        }
+        for (a, b) in self.recorded.iter_mut().zip(&other.recorded) {
+            *a += b;
+        }
+    }
+
+    pub fn log(&self, scene: &Scene) {
+        for (idx, light) in scene.lights.iter().enumerate() {
+            info!(
+                "Light {} ({}): chosen with probability {:.4}, emitted {}, recorded {}",
+                idx, light.kind(), scene.lights.probability(idx), self.emitted[idx], self.recorded[idx],
+            );
+        }
+    }
+}
+
+/**
+ * Traces a photon from a light chosen by power, calling `store` with every recorded photon and its position.
+ *
+ * Photons arriving at surfaces directly from the light are not recorded, since direct lighting is sampled in the eye pass.
+ */
+pub fn trace_photon(args: &super::Args, scene: &Scene, rng: &mut RenderRng, stats: &mut PhotonStats, mut store: impl FnMut(Point, Photon)) {
+    let vol_dist = rand_distr::Exp::new(1f64 / args.mean_dist).unwrap();
+
+    let (light_idx, prob) = scene.lights.choose(rng);
+    let light = scene.lights.get(light_idx);
+    let mut photon: Photon = light.emit_photon(args.photon_per_iter, rng);
+    // Compensates for the lights not chosen, so the expected flux is the total of each light
+    photon.flux /= prob;
+    stats.emitted[light_idx] += 1;
+
+    let wavelength = Wavelength::sample(args.spectral, rng);
+    photon.flux = wavelength.light(light, &photon.flux);
+
+    // Photon maps are in RGB
+    let mut store = |at: Point, mut photon: Photon| {
+        stats.recorded[light_idx] += 1;
+        photon.flux = wavelength.to_rgb(&photon.flux);
+        store(at, photon);
+    };
+
+    let mut media = MediumStack::default();
+
+    for bounce in 0..BOUNCE_HARD_BOUND {
+        // Breaks if photon has no flux
+        if photon.flux.max() <= EPS {
+            break;
+        }
+
+        let int = scene.intersect(&photon.ray);
+        let dist = int.as_ref().map_or(f64::INFINITY, |int| int.dist);
+
+        // Volumetric lights. The global fog is only outside all objects
+        // TODO: the global fog for photons leaving the scene
+        let fog_step = if int.is_some() && media.is_outside() { rng.sample(vol_dist) } else { f64::INFINITY };
+        let vol_step = media.interaction(scene, &photon.ray, dist, rng).map_or(fog_step, |t| t.min(fog_step));
+        let absorption = media.absorption(wavelength);
+        if vol_step < dist {
+            let at = photon.ray.interpolate(vol_step);
+            photon.flux.component_mul_assign(&transmittance(&absorption, vol_step));
+            store(at, photon.clone());
+
+            // Scattered on with the probability of the albedo, absorbed otherwise
+            let phase = media.phase(args, scene, &at);
+            if rng.gen::<f64>() >= phase.albedo() {
+                break;
+            }
+            photon.ray = Ray::new(at, phase.sample(&photon.ray.dir, rng));
+            continue;
+        }
+
+        // Break if no hit
+        let int = if let Some(r) = int { r } else { break };
+        photon.flux.component_mul_assign(&transmittance(&absorption, int.dist));
+
+        let material = int.material;
+
+        if material.is_lambertian() && bounce > 0 {
+            let mut saved = photon.clone();
+            saved
+                .flux
+                .component_mul_assign(&wavelength.filter(&material.get_lambertian_ratio(&int.uv)));
+            store(photon.ray.interpolate(int.dist), saved);
+        }
+
+        let original_flux = photon.flux;
+        let inc = photon.ray.dir;
+        photon = material.get_photon_reflection(
+            &photon.ray.interpolate(int.dist),
+            &inc,
+            &int.norm,
+            &int.uv,
+            wavelength,
+            rng,
+        );
+        photon.flux = wavelength.filter(&photon.flux).component_mul(&original_flux);
+        media.cross(&int, &inc, &photon.ray.dir);
+
+        // Russian roulette
+        let avgflux = photon.flux.mean();
+        if rng.gen::<f64>() > avgflux {
+            // TODO: compensate lost flux
+            break;
+        }
+    }
+}
+
+/**
+ * Estimates the in-scattered light along the first `dist` units of the ray, from the photons recorded in `media`,
+ * attenuated by their absorption along the ray.
+ *
+ * Sight goes through the global fog, gathering at its interactions, but ends at the first interaction with the
+ * bounded media or the interior of an object, whose distance is also returned.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn gather_volume(
+    args: &super::Args,
+    scene: &Scene,
+    photons: &PhotonMap,
+    ray: &Ray,
+    dist: f64,
+    media: &MediumStack,
+    radius: f64,
+    wavelength: Wavelength,
+    rng: &mut RenderRng,
+) -> (Color, Option<f64>) {
+    let vol_dist = rand_distr::Exp::new(1f64 / args.mean_dist).unwrap();
+
+    let stop = media.interaction(scene, ray, dist, rng);
+    let reach = stop.unwrap_or(dist);
+    let absorption = &media.absorption(wavelength);
+
+    let mut color: Color = Default::default();
+    let mut traveled = 0f64;
+    // Nothing from the global fog past the last surface
+    if reach.is_finite() && media.is_outside() {
+        for _vol_cnt in 0..=10 {
+            traveled += rng.sample(vol_dist);
+            if traveled > reach {
+                break;
+            }
+
+            let at = ray.interpolate(traveled);
+            let phase = media.phase(args, scene, &at);
+            color += photon_density(args, photons, &at, &ray.dir, &phase, radius, wavelength)
+                .component_mul(&transmittance(absorption, traveled));
+        }
+    }
+
+    if let Some(t) = stop {
+        let at = ray.interpolate(t);
+        let phase = media.phase(args, scene, &at);
+        color += photon_density(args, photons, &at, &ray.dir, &phase, radius, wavelength).component_mul(&transmittance(absorption, t));
+    }
+
+    (color, stop)
+}
+
+/**
+ * Density of the photons recorded in the media around `at`, within `radius` times the volumetric radius ratio and
+ * weighted by a cone filter. Each photon is also weighted by `phase` of scattering towards the viewer looking along
+ * `view`, relative to isotropic scattering
+ */
+fn photon_density(
+    args: &super::Args,
+    photons: &PhotonMap,
+    at: &Point,
+    view: &Dir,
+    phase: &PhaseMixture,
+    radius: f64,
+    wavelength: Wavelength,
+) -> Color {
+    use kdtree::distance::squared_euclidean;
+
+    let radius = radius * args.volumetric_radius_ratio;
+    let radius3 = radius.powi(3);
+    let photons = photons
+        .within(at.as_ref(), radius3, &squared_euclidean)
+        .unwrap_or_else(|_| panic!("Invalid gathering point {}", at));
+    if photons.is_empty() {
+        return Color::zeros();
+    }
+
+    let mut batch_flux: Color = Default::default();
+    for (dist, photon) in photons {
+        let weight = 1f64 - dist / (args.k * radius);
+        if weight <= EPS {
+            continue;
+        }
+        let cos = -photon.ray.dir.dot(view) / (photon.ray.dir.norm() * view.norm());
+        batch_flux += photon.flux * (weight * 4f64 * core::f64::consts::PI * phase.eval(cos));
+    }
+
+    let batch_flux = batch_flux
+        / (1f64 - (3f64 / 4f64) * args.k)
+        / (radius3 * core::f64::consts::PI);
+
+    wavelength.radiance(&batch_flux)
+}
+
+/**
+ * Saves the checkpoint and images after `done` iterations. `pixels` is the sum over all iterations
+ */
+pub fn save(args: &super::Args, pixels: &[Vec<Color>], done: usize) {
+    info!("Saving checkpoint: {}", done);
+
+    let cps = Checkpoint {
+        iter: done,
+        integrator: Some(args.integrator),
+        seed: args.seed,
+        data: Cow::Borrowed(pixels),
+    };
+
+    cps.save(format!("./checkpoint.{}.json", done).as_ref()).unwrap();
+
+    super::output::write_all(
+        pixels,
+        done,
+        &args.output_format,
+        &args.tone_mapping(),
+        &format!("./output.{}", done),
+    ).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    /**
+     * Checkpoints of a tiny render of the built-in box scene, continuing from `resume` if given
+     */
+    fn render_box(integrator: &str, seed: u64, resume: Option<Checkpoint>) -> Vec<(usize, RenderBuffer)> {
+        let args = crate::Args::from_iter(&[
+            "meow_lens", "--width", "8", "--height", "6", "--iter", "3", "--checkpoint", "2",
+            "--photon-per-iter", "2000", "--threads", "3", "--builtin", "box",
+            "--integrator", integrator, "--seed", &seed.to_string(),
+        ]);
+        let scene = crate::scene::builtin("box").unwrap()(&args);
+
+        let mut checkpoints = Vec::new();
+        let on_checkpoint = |pixels: &[Vec<Color>], done| checkpoints.push((done, pixels.to_vec()));
+        match args.integrator {
+            Integrator::Ppm => ppm::render(&args, &scene, seed, resume, on_checkpoint),
+            Integrator::Sppm => sppm::render(&args, &scene, seed, on_checkpoint),
+            Integrator::Path => path::render(&args, &scene, seed, resume, on_checkpoint),
+        }
+        checkpoints
+    }
+
+    #[test]
+    fn same_seed_renders_identically() {
+        for integrator in &["ppm", "sppm", "path"] {
+            let first = render_box(integrator, 42, None);
+            let second = render_box(integrator, 42, None);
+
+            assert_eq!(first.iter().map(|(done, _)| *done).collect::<Vec<_>>(), vec![2, 3], "{}", integrator);
+            let lit = first.last().unwrap().1.iter().flatten().any(|c| c.max() > 0f64);
+            assert!(lit, "{} renders a black image", integrator);
+            assert!(first == second, "{} renders differently with the same seed", integrator);
+        }
+    }
+
+    #[test]
+    fn resuming_matches_an_uninterrupted_render() {
+        for integrator in &["ppm", "path"] {
+            let full = render_box(integrator, 7, None);
+            let (done, data) = full[0].clone();
+            let resume = Checkpoint {
+                iter: done,
+                integrator: integrator.parse().ok(),
+                seed: Some(7),
+                data: Cow::Owned(data),
+            };
+            let resumed = render_box(integrator, 7, Some(resume));
+
+            assert_eq!(resumed.iter().map(|(done, _)| *done).collect::<Vec<_>>(), vec![3], "{}", integrator);
+            // Summed up in a different order
+            let (expected, found) = (full[1].1.iter().flatten(), resumed[0].1.iter().flatten());
+            for (e, f) in expected.zip(found) {
+                assert!((e - f).norm() <= 1e-9f64 * (1f64 + e.norm()), "{}: {} != {}", integrator, e, f);
+            }
+        }
+    }
+}
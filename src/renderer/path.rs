@@ -0,0 +1,210 @@
+use super::*;
+use crate::checkpoint::Checkpoint;
+use crate::scene::Scene;
+use crate::spectrum::Wavelength;
+use std::f64::consts::PI;
+
+/**
+ * Weight of the sample from a strategy with the density `pdf` against another with `other` (balance heuristic).
+ * Samples of a single point or direction can't be drawn by the other strategy
+ */
+fn balance(pdf: f64, other: f64) -> f64 {
+    if pdf.is_infinite() {
+        return 1f64;
+    }
+    if pdf <= 0f64 {
+        return 0f64;
+    }
+    pdf / (pdf + other)
+}
+
+/**
+ * Density of the next event estimation choosing the direction of `ray` towards what it hits at `dist`
+ */
+fn light_pdf(scene: &Scene, ray: &Ray, dist: f64) -> f64 {
+    scene.lights.iter().map(|light| light.direct_pdf(&ray.origin, &ray.dir, dist)).sum()
+}
+
+/**
+ * Traces one eye path, as a reference for the photon mapping integrators. Values are scaled like theirs, i.e. pi
+ * times the radiance.
+ *
+ * Diffuse surfaces and media sample both the lights (next event estimation) and their own scattering, combined by
+ * multiple importance sampling, so emitters and the environment hit by chance are not counted twice. For mixed
+ * materials, the diffuse part is chosen like in `sppm`. Light through specular bounces (caustics) comes from emitters
+ * and the environment hit after them.
+ *
+ * Media scatter the fraction `albedo` of the light interacting with them, while the photon integrators show all of it.
+ * The global fog, which has no albedo, is taken to scatter everything.
+ *
+ * Remaining bias:
+ * - Caustics from point, spot, semisphere, beam and directional lights are missing, as paths can't hit them.
+ * - Paths end after `BOUNCE_HARD_BOUND` segments.
+ * - Like the photons, the global fog is only on segments ending at surfaces. Shadow rays towards the environment
+ *   still cross it up to the plane its photons are emitted from.
+ * - Direct light from semisphere lights assumes uniform intensity, while their photons favor the diagonals.
+ */
+fn trace(args: &crate::Args, scene: &Scene, x: usize, y: usize, rng: &mut RenderRng) -> Color {
+    let fog_dist = rand_distr::Exp::new(1f64 / args.mean_dist).unwrap();
+
+    let mut ray = scene.camera.generate_ray(x, y, rng);
+    let wavelength = Wavelength::sample(args.spectral, rng);
+    let mut throughput = Vector3::new(1f64, 1f64, 1f64);
+    let mut color: Color = Default::default();
+    let mut media = MediumStack::default();
+    // Of sampling the direction of the current segment, infinite from the camera and specular bounces
+    let mut pdf = f64::INFINITY;
+
+    for _bounce in 0..BOUNCE_HARD_BOUND {
+        let int = scene.intersect(&ray);
+        let dist = int.as_ref().map_or(f64::INFINITY, |int| int.dist);
+
+        let fog_step = if int.is_some() && media.is_outside() { rng.sample(fog_dist) } else { f64::INFINITY };
+        let media_step = media.interaction(scene, &ray, dist, rng).unwrap_or(f64::INFINITY);
+        let step = fog_step.min(media_step);
+        let absorption = media.absorption(wavelength);
+
+        if step < dist {
+            throughput.component_mul_assign(&transmittance(&absorption, step));
+            let at = ray.interpolate(step);
+            let mixture = if fog_step <= media_step {
+                let mut mixture = PhaseMixture::default();
+                mixture.add(1f64 / args.mean_dist, &Isotropic, 1f64);
+                mixture
+            } else if media.is_outside() {
+                let mut mixture = PhaseMixture::default();
+                scene.media.mix_into(&at, &mut mixture);
+                mixture
+            } else {
+                media.phase(args, scene, &at)
+            };
+
+            let albedo = mixture.albedo();
+            let direct = direct_light(args, scene, &at, &media, wavelength, rng, |sample| {
+                let density = mixture.scattered(sample.dir.dot(&ray.dir));
+                PI * albedo * density * balance(sample.pdf, density)
+            });
+            color += direct.component_mul(&throughput);
+
+            if albedo <= 0f64 {
+                break;
+            }
+            let dir = mixture.sample(&ray.dir, rng);
+            pdf = mixture.scattered(dir.dot(&ray.dir));
+            throughput *= albedo;
+            ray = Ray::new(at, dir);
+        } else {
+            let int = if let Some(r) = int {
+                r
+            } else {
+                let weight = balance(pdf, light_pdf(scene, &ray, f64::INFINITY));
+                color += wavelength.radiance(&scene.background(&ray.dir)).component_mul(&throughput) * weight;
+                break;
+            };
+            throughput.component_mul_assign(&transmittance(&absorption, int.dist));
+
+            let material = int.material;
+            let norm = int.norm.normalize();
+            let at = ray.interpolate(int.dist);
+
+            // Emitting towards the side opposite to the normal, with the same radiance in all directions
+            let emission = material.emission();
+            if ray.dir.dot(&norm) > 0f64 && emission.max() > 0f64 {
+                let weight = balance(pdf, light_pdf(scene, &ray, int.dist));
+                color += wavelength.radiance(&emission).component_mul(&throughput) * weight;
+            }
+
+            let reflection = material.get_vision_reflection(&at, &ray.dir, &int.norm, &int.uv, wavelength, rng);
+            let mut specular = wavelength.filter(&reflection.throughput);
+            let lambertian = wavelength.filter(&material.get_lambertian_ratio(&int.uv));
+            let diffuse_weight = lambertian.max();
+            let diffuse_prob = if material.is_lambertian() && diffuse_weight > 0f64 {
+                diffuse_weight / (diffuse_weight + specular.max().max(0f64))
+            } else {
+                0f64
+            };
+
+            if rng.gen::<f64>() < diffuse_prob {
+                // Reflecting to the side the surface is seen from
+                let side = if ray.dir.dot(&norm) > 0f64 { -norm } else { norm };
+                let reflectance = lambertian / diffuse_prob;
+
+                let direct = direct_light(args, scene, &at, &media, wavelength, rng, |sample| {
+                    let cos = sample.dir.dot(&side).max(0f64);
+                    cos * balance(sample.pdf, cos / PI)
+                });
+                color += direct.component_mul(&reflectance).component_mul(&throughput);
+
+                let dir = cosine_weighted(&side, rng);
+                pdf = dir.dot(&side) / PI;
+                throughput.component_mul_assign(&reflectance);
+                ray = Ray::new(at, dir);
+            } else {
+                specular /= 1f64 - diffuse_prob;
+                media.cross(&int, &ray.dir, &reflection.out.dir);
+                ray = reflection.out;
+                throughput.component_mul_assign(&specular);
+                pdf = f64::INFINITY;
+            }
+        }
+
+        // Russian roulette
+        let survival = throughput.max().min(1f64);
+        if rng.gen::<f64>() >= survival {
+            break;
+        }
+        throughput /= survival;
+    }
+
+    wavelength.to_rgb(&color)
+}
+
+/**
+ * Path tracing, one sample per pixel per iteration (times supersampling).
+ * Pixels of each iteration are split across threads. Continues from `resume` if given.
+ * `on_checkpoint` receives the sum over all iterations done so far.
+ */
+pub fn render(
+    args: &crate::Args,
+    scene: &Scene,
+    seed: u64,
+    resume: Option<Checkpoint>,
+    mut on_checkpoint: impl FnMut(&[Vec<Color>], usize),
+) {
+    let mut pixels: RenderBuffer = vec![vec![Default::default(); args.height]; args.width];
+    let columns_per_thread = args.width.div_ceil(args.threads).max(1);
+
+    let mut done = 0;
+    if let Some(cp) = resume {
+        info!("Resuming from iteration {}", cp.iter);
+        done = cp.iter;
+        pixels = cp.data.into_owned();
+    }
+
+    for iter in done..args.iter {
+        info!("Iter {}", iter);
+
+        crossbeam_utils::thread::scope(|s| {
+            for (tid, columns) in pixels.chunks_mut(columns_per_thread).enumerate() {
+                s.spawn(move |_| {
+                    for (offset, column) in columns.iter_mut().enumerate() {
+                        let x = tid * columns_per_thread + offset;
+                        let mut rng = task_rng(seed, iter, x);
+                        for (y, pixel) in column.iter_mut().enumerate() {
+                            let mut accum: Color = Default::default();
+                            for _ss in 0..args.supersampling {
+                                accum += trace(args, scene, x, y, &mut rng);
+                            }
+                            *pixel += accum / args.supersampling as f64;
+                        }
+                    }
+                });
+            }
+        }).unwrap();
+
+        let done = iter + 1;
+        if done % args.checkpoint == 0 || done == args.iter {
+            on_checkpoint(&pixels, done);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use super::*;
+use crate::checkpoint::Checkpoint;
+use crate::scene::Scene;
+use crate::spectrum::Wavelength;
+
+enum Event {
+    Halt,
+    Process {
+        iter: usize,
+        radius: f64,
+    }
+}
+
+/**
+ * Probabilistic PPM: every iteration is independent, with a fresh photon map and eye paths.
+ * Continues from `resume` if given, the checkpoint should match the image size.
+ * `on_checkpoint` receives the sum over all iterations done so far.
+ */
+pub fn render(
+    args: &crate::Args,
+    scene: &Scene,
+    seed: u64,
+    resume: Option<Checkpoint>,
+    mut on_checkpoint: impl FnMut(&[Vec<Color>], usize),
+) {
+    let mut buffers: Vec<RenderBuffer> = Vec::with_capacity(args.threads);
+    for _t in 0..args.threads {
+        buffers.push(vec![vec![Default::default(); args.height]; args.width]);
+    }
+
+    let mut done = 0;
+    if let Some(cp) = resume {
+        info!("Resuming from iteration {}", cp.iter);
+        done = cp.iter;
+        buffers[0] = cp.data.into_owned();
+    }
+
+    // Main loop
+    while done < args.iter {
+        let batch = args.checkpoint.min(args.iter - done);
+        info!("Checkpoint: {} -> {}", done, done + batch);
+
+        // Of each worker over the batch, logged together afterwards
+        let mut batch_stats = vec![PhotonStats::new(scene.lights.len()); args.threads];
+
+        crossbeam_utils::thread::scope(|s| {
+            // Iterations are statically assigned to workers, so that the buffers are summed up in a fixed order
+            let (dispatchers, consumers): (Vec<_>, Vec<_>) =
+                (0..args.threads).map(|_| crossbeam_channel::bounded(2)).unzip();
+
+            {
+                // Dispatcher
+                s.spawn(move |_| {
+                    for iter in done..done + batch {
+                        // The radius starts over from radius_0 in each checkpoint batch
+                        let radius = radius_at(args, iter % args.checkpoint);
+                        info!("[Dispatcher] Dispatching: {}, {}", iter, radius);
+                        dispatchers[iter % args.threads].send(Event::Process { iter, radius }).unwrap();
+                    }
+
+                    for dispatcher in dispatchers.iter() {
+                        dispatcher.send(Event::Halt).unwrap();
+                    }
+
+                    drop(dispatchers);
+                });
+            }
+
+            // Consumer
+            for (((tid, buf), consumer), worker_stats) in buffers.iter_mut().enumerate().zip(consumers).zip(batch_stats.iter_mut()) {
+                s.spawn(move |_| {
+                    loop {
+                        let ev = consumer.recv().unwrap();
+
+                        let (iter, radius) = match ev {
+                            Event::Halt => break,
+                            Event::Process { iter, radius } => (iter, radius),
+                        };
+
+                        let mut rng = iteration_rng(seed, iter);
+
+                        let mut kdtree: PhotonMap = kdtree::KdTree::new(3);
+
+                        info!("[Worker {}] Iter {}, radius {}", tid, iter, radius);
+
+                        // Photon pass
+                        let mut stats = PhotonStats::new(scene.lights.len());
+                        for _pc in 0..args.photon_per_iter {
+                            trace_photon(args, scene, &mut rng, &mut stats, |at, photon| {
+                                kdtree.add(at.as_ref().to_owned(), photon).unwrap();
+                            });
+                        }
+
+                        info!("[Worker {}] Total recorded photons: {}", tid, kdtree.size());
+                        worker_stats.merge(&stats);
+
+                        // RT Pass
+                        let radius3 = radius.powi(3);
+                        for (x, row) in buf.iter_mut().enumerate() {
+                            for (y, pixel) in row.iter_mut().enumerate() {
+                                let mut accum: Color = Default::default();
+
+                                for _ss in 0..args.supersampling {
+                                    let mut ray = scene.camera.generate_ray(x, y, &mut rng);
+                                    let wavelength = Wavelength::sample(args.spectral, &mut rng);
+                                    let mut throughput = Vector3::new(1f64, 1f64, 1f64);
+                                    let mut color: Color = Default::default();
+                                    // Contributions are not weighted by the throughput, only by the absorption of the media
+                                    let mut media = MediumStack::default();
+                                    let mut attenuation = Vector3::new(1f64, 1f64, 1f64);
+
+                                    for _bounce in 0..BOUNCE_HARD_BOUND {
+                                        let int = scene.intersect(&ray);
+                                        let dist = int.as_ref().map_or(f64::INFINITY, |int| int.dist);
+
+                                        // Volumetric lights
+                                        let (volume, stop) = gather_volume(args, scene, &kdtree, &ray, dist, &media, radius, wavelength, &mut rng);
+                                        color += volume.component_mul(&attenuation);
+                                        if stop.is_some() {
+                                            break;
+                                        }
+
+                                        let int = if let Some(r) = int {
+                                            r
+                                        } else {
+                                            color += wavelength.radiance(&scene.background(&ray.dir)).component_mul(&attenuation);
+                                            break;
+                                        };
+
+                                        attenuation.component_mul_assign(&transmittance(&media.absorption(wavelength), int.dist));
+
+                                        // Apply material
+                                        let material = int.material;
+                                        color += emitted(material, &ray.dir, &int.norm, wavelength).component_mul(&attenuation);
+
+                                        if material.is_lambertian() {
+                                            // Direct lighting
+                                            let cos = ray.dir.angle(&int.norm).cos().abs();
+                                            let at = ray.interpolate(int.dist);
+                                            let direct = direct_irradiance(args, scene, &at, &int.norm, &media, wavelength, &mut rng);
+                                            color += direct
+                                                .component_mul(&wavelength.filter(&material.get_lambertian_ratio(&int.uv)))
+                                                .component_mul(&attenuation)
+                                                * cos;
+
+                                            use kdtree::distance::squared_euclidean;
+                                            let photons = kdtree
+                                                .within(
+                                                    ray.interpolate(int.dist).as_ref(),
+                                                    radius3,
+                                                    &squared_euclidean,
+                                                )
+                                                .unwrap();
+
+                                            if !photons.is_empty() {
+                                                let mut batch_flux: Color = Default::default();
+
+                                                for (dist, photon) in photons {
+                                                    let weight = 1f64 - dist / (args.k * radius);
+                                                    if weight <= EPS {
+                                                        continue;
+                                                    }
+                                                    let inc: Vector3<f64> = photon.flux
+                                                        * (weight
+                                                            * ray.dir.angle(&int.norm).cos().abs());
+                                                    batch_flux += inc;
+                                                }
+
+                                                let batch_flux = batch_flux
+                                                    / (1f64 - (2f64 / 3f64) * args.k)
+                                                    / (radius * radius * core::f64::consts::PI);
+
+                                                color += wavelength.radiance(&batch_flux).component_mul(&attenuation);
+                                            }
+                                        }
+
+                                        let reflection = material.get_vision_reflection(
+                                            &ray.interpolate(int.dist),
+                                            &ray.dir,
+                                            &int.norm,
+                                            &int.uv,
+                                            wavelength,
+                                            &mut rng,
+                                        );
+                                        media.cross(&int, &ray.dir, &reflection.out.dir);
+                                        ray = reflection.out;
+                                        throughput.component_mul_assign(&wavelength.filter(&reflection.throughput));
+
+                                        // Russian roulette
+                                        let max_flux = throughput.max();
+                                        if rng.gen::<f64>() > max_flux {
+                                            // Compensate lost flux
+                                            color.component_mul_assign(&throughput.add_scalar(1f64));
+
+                                            break;
+                                        }
+                                    }
+
+                                    accum += wavelength.to_rgb(&color);
+                                }
+
+                                // Update color
+                                *pixel += accum / args.supersampling as f64;
+                            }
+                        }
+                    }
+                });
+            }
+        }).unwrap();
+
+        let mut stats = PhotonStats::new(scene.lights.len());
+        for worker_stats in batch_stats.iter() {
+            stats.merge(worker_stats);
+        }
+        stats.log(scene);
+
+        // Accumulate results
+        let mut pixels: RenderBuffer =
+            vec![vec![Default::default(); args.height]; args.width];
+
+        for buf in buffers.iter() {
+            for (x, col) in buf.iter().enumerate() {
+                for (y, elem) in col.iter().enumerate() {
+                    pixels[x][y] += elem;
+                }
+            }
+        }
+
+        done += batch;
+        on_checkpoint(&pixels, done);
+    }
+}
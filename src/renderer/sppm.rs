@@ -0,0 +1,228 @@
+use super::*;
+use crate::scene::Scene;
+use crate::spectrum::Wavelength;
+use kdtree::distance::squared_euclidean;
+
+// Every chunk of photons is traced with its own RNG, so the photon map doesn't depend on the thread count
+const PHOTON_CHUNK: usize = 16384;
+
+/**
+ * Statistics of a single pixel, see Hachisuka & Jensen, "Stochastic Progressive Photon Mapping"
+ */
+#[derive(Clone)]
+struct PixelStat {
+    radius: f64,
+    /// Accumulated photon count N
+    count: f64,
+    /// Accumulated flux τ, scaled along with the radius
+    flux: Color,
+    /// Sum of the estimates independent of the pixel radius: direct lighting, and the medium with the global radius
+    direct: Color,
+}
+
+impl PixelStat {
+    fn new(radius: f64) -> Self {
+        Self {
+            radius,
+            count: 0f64,
+            flux: Default::default(),
+            direct: Default::default(),
+        }
+    }
+
+    /**
+     * Radiance summed over all iterations so far, matching the checkpoint convention
+     */
+    fn total(&self) -> Color {
+        self.flux / (self.radius * self.radius * core::f64::consts::PI) + self.direct
+    }
+
+    /**
+     * Progressive update with `found` photons carrying `flux` in this iteration
+     */
+    fn update(&mut self, alpha: f64, found: f64, flux: Color) {
+        if found <= 0f64 {
+            return;
+        }
+
+        let count = self.count + alpha * found;
+        let ratio = count / (self.count + found);
+        self.flux = (self.flux + flux) * ratio;
+        self.radius *= ratio.sqrt();
+        self.count = count;
+    }
+}
+
+/**
+ * Traces the photons of one iteration in parallel, in a fixed order
+ */
+fn photon_pass(args: &crate::Args, scene: &Scene, seed: u64, iter: usize) -> (PhotonMap, PhotonStats) {
+    let chunk_count = args.photon_per_iter.div_ceil(PHOTON_CHUNK);
+
+    let mut chunks = crossbeam_utils::thread::scope(|s| {
+        let handles: Vec<_> = (0..args.threads).map(|tid| {
+            s.spawn(move |_| {
+                (tid..chunk_count).step_by(args.threads).map(|chunk| {
+                    let mut rng = task_rng(seed, iter, chunk);
+                    let count = PHOTON_CHUNK.min(args.photon_per_iter - chunk * PHOTON_CHUNK);
+
+                    let mut recorded = Vec::new();
+                    let mut stats = PhotonStats::new(scene.lights.len());
+                    for _pc in 0..count {
+                        trace_photon(args, scene, &mut rng, &mut stats, |at, photon| recorded.push((at, photon)));
+                    }
+                    (chunk, recorded, stats)
+                }).collect::<Vec<_>>()
+            })
+        }).collect();
+
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<_>>()
+    }).unwrap();
+    chunks.sort_by_key(|(chunk, _, _)| *chunk);
+
+    let mut kdtree: PhotonMap = kdtree::KdTree::new(3);
+    let mut stats = PhotonStats::new(scene.lights.len());
+    for (_, recorded, chunk_stats) in chunks {
+        stats.merge(&chunk_stats);
+        for (at, photon) in recorded {
+            kdtree.add(at.as_ref().to_owned(), photon).unwrap();
+        }
+    }
+    (kdtree, stats)
+}
+
+/**
+ * Traces the eye paths of one pixel, and updates its statistics with direct lighting and the photons around the visible points.
+ *
+ * The path continues through specular bounces until a diffuse surface is chosen. For mixed materials, the diffuse
+ * part is chosen with a probability proportional to its ratio against the sampled specular throughput.
+ */
+#[allow(clippy::too_many_arguments)]
+fn eye_pass(args: &crate::Args, scene: &Scene, photons: &PhotonMap, stat: &mut PixelStat, x: usize, y: usize, vol_radius: f64, rng: &mut RenderRng) {
+    let samples = args.supersampling as f64;
+    let mut found = 0f64;
+    let mut flux: Color = Default::default();
+
+    for _ss in 0..args.supersampling {
+        let mut ray = scene.camera.generate_ray(x, y, rng);
+        let wavelength = Wavelength::sample(args.spectral, rng);
+        let mut throughput = Vector3::new(1f64, 1f64, 1f64);
+        let mut media = MediumStack::default();
+
+        for _bounce in 0..BOUNCE_HARD_BOUND {
+            let int = scene.intersect(&ray);
+            let dist = int.as_ref().map_or(f64::INFINITY, |int| int.dist);
+
+            let (volume, stop) = gather_volume(args, scene, photons, &ray, dist, &media, vol_radius, wavelength, rng);
+            stat.direct += wavelength.to_rgb(&volume.component_mul(&throughput)) / samples;
+            if stop.is_some() {
+                break;
+            }
+
+            let int = if let Some(r) = int {
+                r
+            } else {
+                let background = wavelength.radiance(&scene.background(&ray.dir));
+                stat.direct += wavelength.to_rgb(&background.component_mul(&throughput)) / samples;
+                break;
+            };
+
+            throughput.component_mul_assign(&transmittance(&media.absorption(wavelength), int.dist));
+
+            let material = int.material;
+            let emission = emitted(material, &ray.dir, &int.norm, wavelength);
+            stat.direct += wavelength.to_rgb(&emission.component_mul(&throughput)) / samples;
+
+            let at = ray.interpolate(int.dist);
+            let reflection = material.get_vision_reflection(&at, &ray.dir, &int.norm, &int.uv, wavelength, rng);
+
+            let mut specular = wavelength.filter(&reflection.throughput);
+            let lambertian = wavelength.filter(&material.get_lambertian_ratio(&int.uv));
+            let diffuse_weight = lambertian.max();
+            if material.is_lambertian() && diffuse_weight > 0f64 {
+                let specular_weight = specular.max().max(0f64);
+                let diffuse_prob = diffuse_weight / (diffuse_weight + specular_weight);
+
+                if rng.gen::<f64>() < diffuse_prob {
+                    // Visible point, photons already carry the lambertian ratio
+                    let cos = ray.dir.angle(&int.norm).cos().abs();
+                    let direct = direct_irradiance(args, scene, &at, &int.norm, &media, wavelength, rng);
+                    stat.direct += wavelength.to_rgb(&direct.component_mul(&lambertian).component_mul(&throughput))
+                        * (cos / diffuse_prob / samples);
+
+                    let nearby = photons
+                        .within(at.as_ref(), stat.radius * stat.radius, &squared_euclidean)
+                        .unwrap();
+
+                    found += nearby.len() as f64 / samples;
+                    let gathered = nearby.iter().fold(Color::default(), |acc, (_, photon)| acc + photon.flux);
+                    flux += wavelength.to_rgb(&wavelength.radiance(&gathered).component_mul(&throughput))
+                        * (cos / diffuse_prob / samples);
+                    break;
+                }
+
+                specular /= 1f64 - diffuse_prob;
+            }
+
+            media.cross(&int, &ray.dir, &reflection.out.dir);
+            ray = reflection.out;
+            throughput.component_mul_assign(&specular);
+
+            // Russian roulette
+            let survival = throughput.max().min(1f64);
+            if rng.gen::<f64>() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    stat.update(args.alpha, found, flux);
+}
+
+/**
+ * Stochastic PPM. Pixel statistics persist across iterations, so iterations are sequential,
+ * while the photon pass and the pixels of each iteration are split across threads.
+ * `on_checkpoint` receives the total of the pixel statistics.
+ */
+pub fn render(
+    args: &crate::Args,
+    scene: &Scene,
+    seed: u64,
+    mut on_checkpoint: impl FnMut(&[Vec<Color>], usize),
+) {
+    let mut stats = vec![vec![PixelStat::new(args.radius_0); args.height]; args.width];
+    let columns_per_thread = args.width.div_ceil(args.threads).max(1);
+    let chunk_count = args.photon_per_iter.div_ceil(PHOTON_CHUNK);
+
+    for iter in 0..args.iter {
+        let vol_radius = radius_at(args, iter);
+        info!("Iter {}, volumetric radius {}", iter, vol_radius);
+
+        let (photons, photon_stats) = photon_pass(args, scene, seed, iter);
+        info!("Total recorded photons: {}", photons.size());
+        photon_stats.log(scene);
+
+        crossbeam_utils::thread::scope(|s| {
+            for (tid, columns) in stats.chunks_mut(columns_per_thread).enumerate() {
+                let photons = &photons;
+                s.spawn(move |_| {
+                    for (offset, column) in columns.iter_mut().enumerate() {
+                        let x = tid * columns_per_thread + offset;
+                        // Tasks after the photon chunks
+                        let mut rng = task_rng(seed, iter, chunk_count + x);
+                        for (y, stat) in column.iter_mut().enumerate() {
+                            eye_pass(args, scene, photons, stat, x, y, vol_radius, &mut rng);
+                        }
+                    }
+                });
+            }
+        }).unwrap();
+
+        let done = iter + 1;
+        if done % args.checkpoint == 0 || done == args.iter {
+            let pixels: RenderBuffer = stats.iter().map(|col| col.iter().map(PixelStat::total).collect()).collect();
+            on_checkpoint(&pixels, done);
+        }
+    }
+}
@@ -0,0 +1,1259 @@
+use super::Scene;
+use crate::camera::Camera;
+use crate::environment::{Environment, EnvironmentLight};
+use crate::consts::*;
+use crate::light::*;
+use crate::medium::*;
+use crate::phase::*;
+use crate::material::conductor::{metal_ior, metal_names, Conductor};
+use crate::material::dielectric::Dielectric;
+use crate::material::emissive::Emissive;
+use crate::material::general::General;
+use crate::material::Material;
+use crate::object::geometry::mesh::Mesh;
+use crate::object::geometry::sphere::Sphere;
+use crate::object::geometry::triangle::Triangle;
+use crate::object::geometry::{Geometry, GeometryGroup, GeometryObject, Surface};
+use crate::object::{BoundingBox, Object};
+use crate::renderer::*;
+use crate::spectrum::{Ior, Spectrum, Wavelength, LAMBDA_MAX, LAMBDA_MIN};
+use crate::texture::*;
+use crate::Args;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(std::io::Error),
+    Parse { path: String, msg: String },
+    Invalid { path: String, msg: String },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(e) => write!(f, "{}", e),
+            SceneFileError::Parse { path, msg } => write!(f, "{}: {}", path, msg),
+            SceneFileError::Invalid { path, msg } => write!(f, "{}: {}", path, msg),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl From<std::io::Error> for SceneFileError {
+    fn from(e: std::io::Error) -> Self {
+        SceneFileError::Io(e)
+    }
+}
+
+fn invalid<T>(path: impl Into<String>, msg: impl Into<String>) -> Result<T, SceneFileError> {
+    Err(SceneFileError::Invalid {
+        path: path.into(),
+        msg: msg.into(),
+    })
+}
+
+fn one() -> Color {
+    Color::new(1f64, 1f64, 1f64)
+}
+
+fn unit() -> f64 {
+    1f64
+}
+
+fn unit_ior() -> IorDesc {
+    IorDesc::Constant(1f64)
+}
+
+fn default_up() -> Dir {
+    Dir::new(0f64, 1f64, 0f64)
+}
+
+fn default_fovy() -> f64 {
+    50f64
+}
+
+fn default_divisions() -> f64 {
+    8f64
+}
+
+fn default_density() -> DensityDesc {
+    DensityDesc::Constant
+}
+
+fn default_phase() -> PhaseDesc {
+    PhaseDesc::Isotropic
+}
+
+fn default_frequency() -> f64 {
+    4f64
+}
+
+fn default_octaves() -> usize {
+    4
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    camera: CameraDesc,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    models: Vec<ModelDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    #[serde(default)]
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    media: Vec<MediumDesc>,
+
+    /// Directory containing the scene file, relative paths are resolved against it
+    #[serde(skip)]
+    base: PathBuf,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    origin: Point,
+    dir: Dir,
+    #[serde(default = "default_up")]
+    up: Dir,
+    /// Vertical field of view, in degrees
+    #[serde(default = "default_fovy")]
+    fovy: f64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+#[allow(clippy::large_enum_variant)]
+enum MaterialDesc {
+    General {
+        #[serde(default)]
+        diffusion_ratio: f64,
+        #[serde(default)]
+        pure_reflection_ratio: f64,
+        #[serde(default)]
+        refraction_ratio: f64,
+        #[serde(default = "one")]
+        refraction_throughput: Color,
+        #[serde(default = "one")]
+        diffusion_throughput: Color,
+        #[serde(default = "unit_ior")]
+        n1: IorDesc,
+        #[serde(default = "unit_ior")]
+        n2: IorDesc,
+        #[serde(default)]
+        glossy_stddev: f64,
+        /// Multiplied onto `diffusion_throughput`
+        #[serde(default)]
+        diffusion_texture: Option<TextureDesc>,
+        /// Multiplied onto `refraction_throughput`
+        #[serde(default)]
+        refraction_texture: Option<TextureDesc>,
+        #[serde(default)]
+        normal_map: Option<NormalMapDesc>,
+        /// Per unit distance traveled inside
+        #[serde(default)]
+        absorption: Color,
+    },
+    /// Rough metal, given either a `metal` preset or its complex IOR `eta` + i `k`
+    Conductor {
+        #[serde(default)]
+        metal: Option<String>,
+        #[serde(default)]
+        eta: Option<Color>,
+        #[serde(default)]
+        k: Option<Color>,
+        /// Perceptual GGX roughness within [0, 1], 0 being a perfect mirror
+        #[serde(default)]
+        roughness: f64,
+    },
+    /// Rough glass between IORs `n1` outside and `n2` inside
+    Dielectric {
+        #[serde(default = "unit_ior")]
+        n1: IorDesc,
+        n2: IorDesc,
+        #[serde(default = "one")]
+        throughput: Color,
+        #[serde(default)]
+        roughness: f64,
+        /// Per unit distance traveled inside
+        #[serde(default)]
+        absorption: Color,
+    },
+    /// Objects using it become area lights
+    Emissive {
+        /// Emitted flux per unit area
+        emission: Color,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Constant {
+        color: Color,
+    },
+    Checkerboard {
+        even: Color,
+        odd: Color,
+        /// Squares along both u and v within [0, 1]
+        #[serde(default = "default_divisions")]
+        divisions: f64,
+    },
+    /// .hdr and .pfm are linear, other formats are sRGB
+    Image {
+        path: PathBuf,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum NormalMapDesc {
+    /// Tangent space normals encoded as colors, with +X along increasing u and +Z outwards
+    Tangent { texture: TextureDesc },
+    /// Displacement outwards by the average of the channels, times `scale`
+    Bump {
+        height: TextureDesc,
+        #[serde(default = "unit")]
+        scale: f64,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+    geometry: GeometryDesc,
+    material: String,
+    #[serde(default)]
+    medium: Option<InteriorMediumDesc>,
+}
+
+/**
+ * Homogeneous medium filling a closed object
+ */
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct InteriorMediumDesc {
+    /// Per unit length, replacing the absorption of the material
+    #[serde(default)]
+    absorption: Color,
+    /// Interactions per unit length
+    #[serde(default)]
+    scattering: f64,
+    #[serde(default = "default_phase")]
+    phase: PhaseDesc,
+    /// Fraction of the interactions scattering photons on
+    #[serde(default)]
+    albedo: f64,
+}
+
+/**
+ * Wavefront OBJ model, using materials from its MTL file unless `material` is given
+ */
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ModelDesc {
+    path: PathBuf,
+    #[serde(default = "unit")]
+    scale: f64,
+    #[serde(default)]
+    translate: Point,
+    #[serde(default)]
+    material: Option<String>,
+}
+
+/**
+ * Equirectangular environment map in .hdr or .pfm, also lighting the scene
+ */
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    path: PathBuf,
+    #[serde(default = "unit")]
+    scale: f64,
+    /// Around +Y, in degrees
+    #[serde(default)]
+    rotation: f64,
+}
+
+/**
+ * Participating medium within an axis-aligned box
+ */
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct MediumDesc {
+    lower: Point,
+    upper: Point,
+    /// Interactions per unit length at a density of 1
+    scattering: f64,
+    #[serde(default = "default_density")]
+    density: DensityDesc,
+    #[serde(default = "default_phase")]
+    phase: PhaseDesc,
+    /// Fraction of the interactions scattering photons on
+    #[serde(default)]
+    albedo: f64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PhaseDesc {
+    Isotropic,
+    HenyeyGreenstein { g: f64 },
+    Rayleigh,
+    /// Cornette-Shanks approximation
+    Mie { g: f64 },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DensityDesc {
+    Constant,
+    /// Mitsuba's .vol grid, stretched over the bounds
+    Grid { path: PathBuf },
+    Noise {
+        /// Noise cells across the bounds
+        #[serde(default = "default_frequency")]
+        frequency: f64,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        /// Within [0, 1), noise below which is empty
+        #[serde(default)]
+        threshold: f64,
+        #[serde(default)]
+        seed: u32,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum GeometryDesc {
+    Sphere { center: Point, radius: f64 },
+    Box { lower: Point, upper: Point },
+    Triangle {
+        vertices: [Point; 3],
+        #[serde(default)]
+        uvs: Option<[UV; 3]>,
+    },
+    Mesh {
+        vertices: Vec<Point>,
+        faces: Vec<[usize; 3]>,
+        /// One for each vertex
+        #[serde(default)]
+        uvs: Option<Vec<UV>>,
+        /// One for each vertex, on the same side as the face normals
+        #[serde(default)]
+        normals: Option<Vec<Dir>>,
+        /// Averages vertex normals from the adjacent faces if `normals` are absent
+        #[serde(default)]
+        smooth: bool,
+    },
+}
+
+/// A number, or a formula varying with the wavelength
+#[derive(Debug)]
+enum IorDesc {
+    Constant(f64),
+    Dispersive(DispersiveIorDesc),
+}
+
+/// Wavelengths in μm
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DispersiveIorDesc {
+    Cauchy {
+        a: f64,
+        b: f64,
+        #[serde(default)]
+        c: f64,
+    },
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+/// RGB, or a spectral power distribution
+#[derive(Debug)]
+enum LightColorDesc {
+    Rgb(Color),
+    Spectrum(SpectrumDesc),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum SpectrumDesc {
+    Blackbody {
+        /// In Kelvin
+        temperature: f64,
+        #[serde(default = "unit")]
+        luminance: f64,
+    },
+    /// Linearly interpolated, zero outside the given wavelengths
+    Sampled {
+        /// In nm, ascending
+        wavelengths: Vec<f64>,
+        values: Vec<f64>,
+        #[serde(default = "unit")]
+        luminance: f64,
+    },
+}
+
+/**
+ * Picks the variant by the shape of the value instead of trying both, so the
+ * error of an invalid formula comes from the formula itself
+ */
+impl<'de> Deserialize<'de> for IorDesc {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct IorVisitor;
+
+        impl<'de> Visitor<'de> for IorVisitor {
+            type Value = IorDesc;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number, or a table with `type` cauchy or sellmeier")
+            }
+
+            fn visit_f64<E: de::Error>(self, n: f64) -> Result<IorDesc, E> {
+                Ok(IorDesc::Constant(n))
+            }
+
+            fn visit_i64<E: de::Error>(self, n: i64) -> Result<IorDesc, E> {
+                Ok(IorDesc::Constant(n as f64))
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<IorDesc, E> {
+                Ok(IorDesc::Constant(n as f64))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<IorDesc, A::Error> {
+                DispersiveIorDesc::deserialize(MapAccessDeserializer::new(map)).map(IorDesc::Dispersive)
+            }
+        }
+
+        de.deserialize_any(IorVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for LightColorDesc {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct LightColorVisitor;
+
+        impl<'de> Visitor<'de> for LightColorVisitor {
+            type Value = LightColorDesc;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an RGB array, or a table with `type` blackbody or sampled")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<LightColorDesc, A::Error> {
+                Color::deserialize(SeqAccessDeserializer::new(seq)).map(LightColorDesc::Rgb)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<LightColorDesc, A::Error> {
+                SpectrumDesc::deserialize(MapAccessDeserializer::new(map)).map(LightColorDesc::Spectrum)
+            }
+        }
+
+        de.deserialize_any(LightColorVisitor)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Semisphere {
+        at: Point,
+        color: LightColorDesc,
+        total_flux: f64,
+        towards: Dir,
+    },
+    Beam {
+        origin: Point,
+        radius: f64,
+        dir: Dir,
+        reference: Dir,
+        color: LightColorDesc,
+        total_flux: f64,
+    },
+    Point {
+        at: Point,
+        color: LightColorDesc,
+        total_flux: f64,
+    },
+    Spot {
+        at: Point,
+        dir: Dir,
+        /// Full intensity within this angle from `dir`, in degrees
+        inner_angle: f64,
+        /// No light beyond this angle from `dir`, in degrees
+        outer_angle: f64,
+        color: LightColorDesc,
+        total_flux: f64,
+    },
+    /// Emits from outside the bounding box of all objects
+    Directional {
+        dir: Dir,
+        color: LightColorDesc,
+        /// On surfaces perpendicular to `dir`
+        irradiance: f64,
+    },
+}
+
+fn check_dir(path: String, dir: &Dir) -> Result<(), SceneFileError> {
+    if dir.norm() < EPS {
+        return invalid(path, "direction must not be a zero vector");
+    }
+    Ok(())
+}
+
+fn check_positive(path: String, value: f64) -> Result<(), SceneFileError> {
+    if !value.is_finite() || value <= 0f64 {
+        return invalid(path, format!("must be a positive number, got {}", value));
+    }
+    Ok(())
+}
+
+fn check_non_negative(path: String, value: f64) -> Result<(), SceneFileError> {
+    if !value.is_finite() || value < 0f64 {
+        return invalid(path, format!("must be a non-negative number, got {}", value));
+    }
+    Ok(())
+}
+
+fn check_roughness(path: String, value: f64) -> Result<(), SceneFileError> {
+    if !(0f64..=1f64).contains(&value) {
+        return invalid(path, format!("must be within [0, 1], got {}", value));
+    }
+    Ok(())
+}
+
+fn check_color(path: String, color: &Color) -> Result<(), SceneFileError> {
+    if color.iter().any(|c| !c.is_finite() || *c < 0f64) {
+        return invalid(path, format!("color components must be non-negative, got {:?}", color.as_slice()));
+    }
+    Ok(())
+}
+
+impl MediumDesc {
+    fn build(&self, path: &str, base: &Path) -> Result<Medium, SceneFileError> {
+        let (lower, upper) = (self.lower, self.upper);
+        if lower.iter().chain(upper.iter()).any(|c| !c.is_finite()) {
+            return invalid(path, "bounds must be finite");
+        }
+        if (0..3).any(|i| lower[i] >= upper[i]) {
+            return invalid(
+                format!("{}.upper", path),
+                format!("must be strictly greater than `lower` on every axis, got {:?} and {:?}", lower.as_slice(), upper.as_slice()),
+            );
+        }
+        check_non_negative(format!("{}.scattering", path), self.scattering)?;
+
+        let density: Arc<dyn Density> = match &self.density {
+            DensityDesc::Constant => Arc::new(ConstantDensity),
+            DensityDesc::Grid { path: file } => {
+                let file = base.join(file);
+                match GridDensity::load(&file) {
+                    Ok(grid) => Arc::new(grid),
+                    Err(e) => return invalid(format!("{}.density.path", path), format!("failed to load {}: {}", file.display(), e)),
+                }
+            }
+            DensityDesc::Noise { frequency, octaves, threshold, seed } => {
+                check_positive(format!("{}.density.frequency", path), *frequency)?;
+                if *octaves == 0 || *octaves > 16 {
+                    return invalid(format!("{}.density.octaves", path), format!("must be within [1, 16], got {}", octaves));
+                }
+                if !(0f64..1f64).contains(threshold) {
+                    return invalid(format!("{}.density.threshold", path), format!("must be within [0, 1), got {}", threshold));
+                }
+                Arc::new(NoiseDensity::new(*frequency, *octaves, *threshold, *seed))
+            }
+        };
+
+        let bounds = BoundingBox {
+            x: (lower[0], upper[0]),
+            y: (lower[1], upper[1]),
+            z: (lower[2], upper[2]),
+        };
+        let (phase, albedo) = build_phase(path, &self.phase, self.albedo)?;
+        Ok(Medium::new(bounds, density, self.scattering).with_phase(phase, albedo))
+    }
+}
+
+/**
+ * Phase function and albedo of the medium at `path`
+ */
+fn build_phase(path: &str, phase: &PhaseDesc, albedo: f64) -> Result<(Arc<dyn PhaseFunction>, f64), SceneFileError> {
+    if !(0f64..=1f64).contains(&albedo) {
+        return invalid(format!("{}.albedo", path), format!("must be within [0, 1], got {}", albedo));
+    }
+
+    let check_g = |g: f64| {
+        if !(g > -1f64 && g < 1f64) {
+            return invalid(format!("{}.phase.g", path), format!("must be within (-1, 1), got {}", g));
+        }
+        Ok(())
+    };
+    let phase: Arc<dyn PhaseFunction> = match *phase {
+        PhaseDesc::Isotropic => Arc::new(Isotropic),
+        PhaseDesc::HenyeyGreenstein { g } => {
+            check_g(g)?;
+            Arc::new(HenyeyGreenstein::new(g))
+        }
+        PhaseDesc::Rayleigh => Arc::new(Rayleigh),
+        PhaseDesc::Mie { g } => {
+            check_g(g)?;
+            Arc::new(Mie::new(g))
+        }
+    };
+    Ok((phase, albedo))
+}
+
+impl InteriorMediumDesc {
+    fn build(&self, path: &str) -> Result<InteriorMedium, SceneFileError> {
+        check_color(format!("{}.absorption", path), &self.absorption)?;
+        check_non_negative(format!("{}.scattering", path), self.scattering)?;
+        let (phase, albedo) = build_phase(path, &self.phase, self.albedo)?;
+        Ok(InteriorMedium::new(self.absorption, self.scattering).with_phase(phase, albedo))
+    }
+}
+
+impl TextureDesc {
+    /**
+     * `base` is the directory image paths are relative to
+     */
+    fn build(&self, path: &str, base: &Path) -> Result<Arc<dyn Texture>, SceneFileError> {
+        let texture: Arc<dyn Texture> = match self {
+            TextureDesc::Constant { color } => {
+                check_color(format!("{}.color", path), color)?;
+                Arc::new(ConstantTexture::new(*color))
+            }
+            TextureDesc::Checkerboard { even, odd, divisions } => {
+                check_color(format!("{}.even", path), even)?;
+                check_color(format!("{}.odd", path), odd)?;
+                check_positive(format!("{}.divisions", path), *divisions)?;
+                Arc::new(CheckerboardTexture::new(*even, *odd, *divisions))
+            }
+            TextureDesc::Image { path: file } => {
+                let file = base.join(file);
+                match ImageTexture::load(&file) {
+                    Ok(t) => Arc::new(t),
+                    Err(e) => return invalid(format!("{}.path", path), format!("failed to load {}: {}", file.display(), e)),
+                }
+            }
+        };
+        Ok(texture)
+    }
+}
+
+impl NormalMapDesc {
+    fn build(&self, path: &str, base: &Path) -> Result<NormalMap, SceneFileError> {
+        match self {
+            NormalMapDesc::Tangent { texture } => Ok(NormalMap::Tangent(texture.build(&format!("{}.texture", path), base)?)),
+            NormalMapDesc::Bump { height, scale } => {
+                if !scale.is_finite() {
+                    return invalid(format!("{}.scale", path), format!("must be a finite number, got {}", scale));
+                }
+                Ok(NormalMap::Bump {
+                    height: height.build(&format!("{}.height", path), base)?,
+                    scale: *scale,
+                })
+            }
+        }
+    }
+}
+
+impl MaterialDesc {
+    fn build(&self, path: &str, base: &Path) -> Result<Arc<dyn Material>, SceneFileError> {
+        match *self {
+            MaterialDesc::General {
+                diffusion_ratio,
+                pure_reflection_ratio,
+                refraction_ratio,
+                refraction_throughput,
+                diffusion_throughput,
+                ref n1,
+                ref n2,
+                glossy_stddev,
+                ref diffusion_texture,
+                ref refraction_texture,
+                ref normal_map,
+                absorption,
+            } => {
+                check_non_negative(format!("{}.diffusion_ratio", path), diffusion_ratio)?;
+                check_non_negative(format!("{}.pure_reflection_ratio", path), pure_reflection_ratio)?;
+                check_non_negative(format!("{}.refraction_ratio", path), refraction_ratio)?;
+                let total = diffusion_ratio + pure_reflection_ratio + refraction_ratio;
+                if total > 1f64 + EPS {
+                    return invalid(path, format!("ratios must sum up to at most 1, got {}", total));
+                }
+                check_color(format!("{}.refraction_throughput", path), &refraction_throughput)?;
+                check_color(format!("{}.diffusion_throughput", path), &diffusion_throughput)?;
+                let n1 = n1.build(&format!("{}.n1", path))?;
+                let n2 = n2.build(&format!("{}.n2", path))?;
+                check_non_negative(format!("{}.glossy_stddev", path), glossy_stddev)?;
+                check_color(format!("{}.absorption", path), &absorption)?;
+
+                let mut material = General::new(
+                    diffusion_ratio,
+                    pure_reflection_ratio,
+                    refraction_ratio,
+                    refraction_throughput,
+                    diffusion_throughput,
+                    n1.at(Wavelength::Rgb),
+                    n2.at(Wavelength::Rgb),
+                    glossy_stddev,
+                )
+                .with_ior(n1, n2)
+                .with_absorption(absorption);
+                if let Some(desc) = diffusion_texture {
+                    material = material.with_diffusion_texture(desc.build(&format!("{}.diffusion_texture", path), base)?);
+                }
+                if let Some(desc) = refraction_texture {
+                    material = material.with_refraction_texture(desc.build(&format!("{}.refraction_texture", path), base)?);
+                }
+                if let Some(desc) = normal_map {
+                    material = material.with_normal_map(desc.build(&format!("{}.normal_map", path), base)?);
+                }
+                Ok(Arc::new(material))
+            }
+            MaterialDesc::Conductor { ref metal, eta, k, roughness } => {
+                check_roughness(format!("{}.roughness", path), roughness)?;
+                let (eta, k) = match (metal, eta, k) {
+                    (Some(metal), None, None) => match metal_ior(metal) {
+                        Some(ior) => ior,
+                        None => {
+                            let names: Vec<_> = metal_names().collect();
+                            return invalid(format!("{}.metal", path), format!("unknown metal {:?}, expected one of {}", metal, names.join(", ")));
+                        }
+                    },
+                    (None, Some(eta), Some(k)) => {
+                        check_color(format!("{}.eta", path), &eta)?;
+                        check_color(format!("{}.k", path), &k)?;
+                        (eta, k)
+                    }
+                    _ => return invalid(path, "either `metal` or both `eta` and `k` must be given"),
+                };
+                Ok(Arc::new(Conductor::new(eta, k, roughness)))
+            }
+            MaterialDesc::Dielectric { ref n1, ref n2, throughput, roughness, absorption } => {
+                let n1 = n1.build(&format!("{}.n1", path))?;
+                let n2 = n2.build(&format!("{}.n2", path))?;
+                check_color(format!("{}.throughput", path), &throughput)?;
+                check_roughness(format!("{}.roughness", path), roughness)?;
+                check_color(format!("{}.absorption", path), &absorption)?;
+                Ok(Arc::new(Dielectric::new(n1, n2, throughput, roughness).with_absorption(absorption)))
+            }
+            MaterialDesc::Emissive { emission } => {
+                check_color(format!("{}.emission", path), &emission)?;
+                Ok(Arc::new(Emissive::new(emission)))
+            }
+        }
+    }
+}
+
+impl GeometryDesc {
+    /**
+     * Builds the object filled with `medium`, along with an area light over it if the material is emissive
+     */
+    fn build(
+        &self,
+        path: &str,
+        material: Arc<dyn Material>,
+        medium: Option<InteriorMedium>,
+        lights: &mut Vec<Box<dyn Light>>,
+    ) -> Result<Box<dyn Object>, SceneFileError> {
+        let emission = material.emission();
+        let mut emit = |surfaces: Vec<Box<dyn Surface>>| {
+            if emission.max() > 0f64 {
+                lights.push(Box::new(AreaLight::new(surfaces, emission)));
+            }
+        };
+
+        let obj: Box<dyn Object> = match self {
+            GeometryDesc::Sphere { center, radius } => {
+                check_positive(format!("{}.radius", path), *radius)?;
+                let sphere = Sphere::new(*center, *radius);
+                emit(vec![Box::new(sphere.clone())]);
+                filled(GeometryObject::new(sphere, material), medium)
+            }
+            GeometryDesc::Box { lower, upper } => {
+                if (0..3).any(|i| lower[i] >= upper[i]) {
+                    return invalid(
+                        format!("{}.upper", path),
+                        format!("must be strictly greater than `lower` on every axis, got {:?} and {:?}", lower.as_slice(), upper.as_slice()),
+                    );
+                }
+                let triangles = crate::object::geometry::util::create_box(*lower, *upper);
+                emit(triangles.iter().map(|t| Box::new(t.clone()) as Box<dyn Surface>).collect());
+                let geo: GeometryGroup<_> = triangles.into();
+                filled(GeometryObject::new(geo, material), medium)
+            }
+            GeometryDesc::Triangle { vertices, uvs } => {
+                if (vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0])).norm() < EPS {
+                    return invalid(format!("{}.vertices", path), "triangle is degenerated");
+                }
+                let mut triangle = Triangle::new(*vertices);
+                if let Some(uvs) = uvs {
+                    triangle = triangle.with_uvs(*uvs);
+                }
+                emit(vec![Box::new(triangle.clone())]);
+                filled(GeometryObject::new(triangle, material), medium)
+            }
+            GeometryDesc::Mesh { vertices, faces, uvs, normals, smooth } => {
+                if let Some(uvs) = uvs {
+                    if uvs.len() != vertices.len() {
+                        return invalid(
+                            format!("{}.uvs", path),
+                            format!("expected one for each of the {} vertices, got {}", vertices.len(), uvs.len()),
+                        );
+                    }
+                }
+                if let Some(normals) = normals {
+                    if normals.len() != vertices.len() {
+                        return invalid(
+                            format!("{}.normals", path),
+                            format!("expected one for each of the {} vertices, got {}", vertices.len(), normals.len()),
+                        );
+                    }
+                    if *smooth {
+                        return invalid(format!("{}.smooth", path), "conflicts with `normals`");
+                    }
+                }
+                if vertices.len() > u32::MAX as usize {
+                    return invalid(format!("{}.vertices", path), format!("at most {} vertices are supported", u32::MAX));
+                }
+
+                for (i, face) in faces.iter().enumerate() {
+                    if let Some(idx) = face.iter().find(|idx| **idx >= vertices.len()) {
+                        return invalid(
+                            format!("{}.faces[{}]", path, i),
+                            format!("vertex index {} out of range, only {} vertices present", idx, vertices.len()),
+                        );
+                    }
+                }
+                let faces: Vec<[u32; 3]> = faces.iter().map(|face| face.map(|i| i as u32)).collect();
+
+                let normals = if *smooth { Some(Mesh::smooth_normals(vertices, &faces)) } else { normals.clone() };
+                let mesh = Arc::new(Mesh::new(vertices.clone(), faces, normals, uvs.clone()));
+                if mesh.is_empty() {
+                    return invalid(format!("{}.faces", path), "no faces other than degenerated ones");
+                }
+                emit(vec![Box::new(mesh.clone())]);
+                filled(GeometryObject::new(mesh, material), medium)
+            }
+        };
+        Ok(obj)
+    }
+}
+
+fn filled<G: Geometry + 'static>(obj: GeometryObject<G, Arc<dyn Material>>, medium: Option<InteriorMedium>) -> Box<dyn Object> {
+    match medium {
+        Some(medium) => Box::new(obj.with_medium(medium)),
+        None => Box::new(obj),
+    }
+}
+
+impl LightDesc {
+    fn color(&self) -> &LightColorDesc {
+        match self {
+            LightDesc::Semisphere { color, .. }
+            | LightDesc::Beam { color, .. }
+            | LightDesc::Point { color, .. }
+            | LightDesc::Spot { color, .. }
+            | LightDesc::Directional { color, .. } => color,
+        }
+    }
+
+    /**
+     * `bounds` covers all objects, for directional lights
+     */
+    fn build(&self, path: &str, bounds: &BoundingBox) -> Result<Box<dyn Light>, SceneFileError> {
+        let (color, spectrum) = self.color().build(&format!("{}.color", path))?;
+
+        let light: Box<dyn Light> = match *self {
+            LightDesc::Semisphere { at, total_flux, towards, .. } => {
+                check_non_negative(format!("{}.total_flux", path), total_flux)?;
+                check_dir(format!("{}.towards", path), &towards)?;
+                Box::new(SemisphereLight::new(at, color, total_flux, towards))
+            }
+            LightDesc::Beam { origin, radius, dir, reference, total_flux, .. } => {
+                check_positive(format!("{}.radius", path), radius)?;
+                check_dir(format!("{}.dir", path), &dir)?;
+                check_dir(format!("{}.reference", path), &reference)?;
+                if dir.cross(&reference).norm() < EPS * dir.norm() * reference.norm() {
+                    return invalid(format!("{}.reference", path), "must not be parallel to `dir`");
+                }
+                check_non_negative(format!("{}.total_flux", path), total_flux)?;
+                Box::new(BeamLight::new(origin, radius, dir.normalize(), reference, total_flux, color))
+            }
+            LightDesc::Point { at, total_flux, .. } => {
+                check_non_negative(format!("{}.total_flux", path), total_flux)?;
+                Box::new(PointLight::new(at, color, total_flux))
+            }
+            LightDesc::Spot { at, dir, inner_angle, outer_angle, total_flux, .. } => {
+                check_dir(format!("{}.dir", path), &dir)?;
+                if !(outer_angle > 0f64 && outer_angle <= 180f64) {
+                    return invalid(format!("{}.outer_angle", path), format!("must be within (0, 180] degrees, got {}", outer_angle));
+                }
+                if !(inner_angle >= 0f64 && inner_angle <= outer_angle) {
+                    return invalid(format!("{}.inner_angle", path), format!("must be within [0, outer_angle] degrees, got {}", inner_angle));
+                }
+                check_non_negative(format!("{}.total_flux", path), total_flux)?;
+                let to_rad = std::f64::consts::PI / 180f64;
+                Box::new(SpotLight::new(at, dir, inner_angle * to_rad, outer_angle * to_rad, color, total_flux))
+            }
+            LightDesc::Directional { dir, irradiance, .. } => {
+                check_dir(format!("{}.dir", path), &dir)?;
+                check_non_negative(format!("{}.irradiance", path), irradiance)?;
+                if !bounds.extent().iter().all(|e| e.is_finite()) {
+                    return invalid(path, "directional lights require bounded objects in the scene");
+                }
+                Box::new(DirectionalLight::new(dir, color, irradiance, bounds))
+            }
+        };
+
+        Ok(match spectrum {
+            Some(spectrum) => Box::new(SpectralLight::new(light, spectrum)),
+            None => light,
+        })
+    }
+}
+
+impl IorDesc {
+    fn build(&self, path: &str) -> Result<Ior, SceneFileError> {
+        let ior = match *self {
+            IorDesc::Constant(n) => Ior::Constant(n),
+            IorDesc::Dispersive(DispersiveIorDesc::Cauchy { a, b, c }) => Ior::Cauchy { a, b, c },
+            IorDesc::Dispersive(DispersiveIorDesc::Sellmeier { b, c }) => Ior::Sellmeier { b, c },
+        };
+
+        // Also catches poles of Sellmeier's equation within the visible range
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        for i in 0..=steps {
+            let lambda = LAMBDA_MIN + i as f64;
+            let n = ior.at(Wavelength::Sampled(lambda));
+            if !n.is_finite() || n <= 0f64 {
+                return invalid(path, format!("must be positive over the visible range, got {} at {} nm", n, lambda));
+            }
+        }
+        let n = ior.at(Wavelength::Rgb);
+        if !n.is_finite() || n <= 0f64 {
+            return invalid(path, format!("must be positive, got {}", n));
+        }
+        Ok(ior)
+    }
+}
+
+impl LightColorDesc {
+    /**
+     * The RGB color, along with the spectrum it comes from if any
+     */
+    fn build(&self, path: &str) -> Result<(Color, Option<Spectrum>), SceneFileError> {
+        let (spectrum, luminance) = match self {
+            LightColorDesc::Rgb(color) => {
+                check_color(path.to_owned(), color)?;
+                return Ok((*color, None));
+            }
+            LightColorDesc::Spectrum(SpectrumDesc::Blackbody { temperature, luminance }) => {
+                check_positive(format!("{}.temperature", path), *temperature)?;
+                (Spectrum::blackbody(*temperature), *luminance)
+            }
+            LightColorDesc::Spectrum(SpectrumDesc::Sampled { wavelengths, values, luminance }) => {
+                if wavelengths.len() != values.len() || wavelengths.len() < 2 {
+                    return invalid(path, format!("expected `wavelengths` and `values` of the same length, at least 2, got {} and {}", wavelengths.len(), values.len()));
+                }
+                if wavelengths.iter().any(|l| !l.is_finite()) || wavelengths.windows(2).any(|w| w[0] >= w[1]) {
+                    return invalid(format!("{}.wavelengths", path), "must be strictly ascending");
+                }
+                if values.iter().any(|v| !v.is_finite() || *v < 0f64) {
+                    return invalid(format!("{}.values", path), "must be non-negative");
+                }
+                let points: Vec<(f64, f64)> = wavelengths.iter().cloned().zip(values.iter().cloned()).collect();
+                (Spectrum::sampled(&points), *luminance)
+            }
+        };
+
+        check_non_negative(format!("{}.luminance", path), luminance)?;
+        match spectrum {
+            Some(spectrum) => Ok((spectrum.rgb() * luminance, Some(spectrum))),
+            None => invalid(path, "spectrum must not be zero over the visible range"),
+        }
+    }
+}
+
+impl SceneDesc {
+    /**
+     * Parses a scene description. TOML is used for `.toml` files, and JSON for everything else
+     */
+    pub fn load(path: &Path) -> Result<Self, SceneFileError> {
+        let content = std::fs::read_to_string(path)?;
+        let is_toml = path.extension().map(|e| e == "toml").unwrap_or(false);
+
+        let parsed = if is_toml {
+            let mut de = toml::Deserializer::new(&content);
+            serde_path_to_error::deserialize(&mut de).map_err(|e| (e.path().to_string(), e.into_inner().to_string()))
+        } else {
+            let mut de = serde_json::Deserializer::from_str(&content);
+            serde_path_to_error::deserialize(&mut de).map_err(|e| (e.path().to_string(), e.into_inner().to_string()))
+        };
+
+        let mut desc: Self = parsed.map_err(|(path, msg)| SceneFileError::Parse { path, msg })?;
+        desc.base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(desc)
+    }
+
+    pub fn build(&self, args: &Args) -> Result<Scene, SceneFileError> {
+        check_dir("camera.dir".to_owned(), &self.camera.dir)?;
+        check_dir("camera.up".to_owned(), &self.camera.up)?;
+        if self.camera.dir.cross(&self.camera.up).norm() < EPS * self.camera.dir.norm() * self.camera.up.norm() {
+            return invalid("camera.up", "must not be parallel to `camera.dir`");
+        }
+        if !(self.camera.fovy > 0f64 && self.camera.fovy < 180f64) {
+            return invalid("camera.fovy", format!("must be within (0, 180) degrees, got {}", self.camera.fovy));
+        }
+
+        let camera = Camera::new(
+            self.camera.origin,
+            self.camera.dir.normalize(),
+            self.camera.up,
+            args.width,
+            args.height,
+            self.camera.fovy * std::f64::consts::PI / 180f64,
+            args.lens_radius,
+            args.depth,
+        );
+
+        let mut materials = BTreeMap::new();
+        for (name, desc) in self.materials.iter() {
+            materials.insert(name.as_str(), desc.build(&format!("materials.{}", name), &self.base)?);
+        }
+
+        let mut lights = Vec::with_capacity(self.lights.len());
+        let mut objs: Vec<Box<dyn Object>> = Vec::with_capacity(self.objects.len());
+        for (i, desc) in self.objects.iter().enumerate() {
+            let path = format!("objects[{}]", i);
+            let material = if let Some(m) = materials.get(desc.material.as_str()) {
+                m.clone()
+            } else {
+                return invalid(format!("{}.material", path), format!("unknown material `{}`", desc.material));
+            };
+            let medium = match &desc.medium {
+                Some(medium) => Some(medium.build(&format!("{}.medium", path))?),
+                None => None,
+            };
+            objs.push(desc.geometry.build(&format!("{}.geometry", path), material, medium, &mut lights)?);
+        }
+
+        for (i, desc) in self.models.iter().enumerate() {
+            let path = format!("models[{}]", i);
+            let material = match &desc.material {
+                None => None,
+                Some(name) => match materials.get(name.as_str()) {
+                    Some(m) => Some(m.clone()),
+                    None => return invalid(format!("{}.material", path), format!("unknown material `{}`", name)),
+                },
+            };
+            check_positive(format!("{}.scale", path), desc.scale)?;
+
+            let file = self.base.join(&desc.path);
+            match super::obj::load(&file, desc.scale, desc.translate, material) {
+                Ok(model) => {
+                    objs.extend(model.objs);
+                    lights.extend(model.lights);
+                }
+                Err(e) => return invalid(format!("{}.path", path), format!("failed to load {}: {}", file.display(), e)),
+            }
+        }
+
+        let bounds = objs.iter().fold(BoundingBox::empty(), |acc, o| acc.merge(&o.bounding_box()));
+        for (i, desc) in self.lights.iter().enumerate() {
+            lights.push(desc.build(&format!("lights[{}]", i), &bounds)?);
+        }
+
+        let environment = match &self.environment {
+            None => None,
+            Some(desc) => {
+                check_non_negative("environment.scale".to_owned(), desc.scale)?;
+                let file = self.base.join(&desc.path);
+                let env = match Environment::load(&file, desc.scale, desc.rotation * std::f64::consts::PI / 180f64) {
+                    Ok(env) => Arc::new(env),
+                    Err(e) => return invalid("environment.path", format!("failed to load {}: {}", file.display(), e)),
+                };
+
+                if !env.is_black() {
+                    if !bounds.extent().iter().all(|e| e.is_finite()) {
+                        return invalid("environment", "environment lighting requires bounded objects in the scene");
+                    }
+                    lights.push(Box::new(EnvironmentLight::new(env.clone(), &bounds)));
+                }
+                Some(env)
+            }
+        };
+
+        let mut media = Vec::with_capacity(self.media.len());
+        for (i, desc) in self.media.iter().enumerate() {
+            media.push(desc.build(&format!("media[{}]", i), &self.base)?);
+        }
+
+        let lights = LightSet::new(lights);
+        if lights.is_empty() {
+            return invalid("lights", "at least one light or emissive object is required");
+        }
+
+        Ok(Scene {
+            objs: objs.into(),
+            lights,
+            camera,
+            environment,
+            media: Media::new(media),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn args() -> Args {
+        Args::from_iter(&["meow_lens"])
+    }
+
+    /**
+     * Loads `content` as a scene file with the extension `ext`, keeping the parse errors apart
+     */
+    fn load(name: &str, ext: &str, content: &str) -> Result<SceneDesc, SceneFileError> {
+        let path = std::env::temp_dir().join(format!("meow_lens_{}_{}.{}", name, std::process::id(), ext));
+        std::fs::write(&path, content).unwrap();
+        let desc = SceneDesc::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        desc
+    }
+
+    fn build_error(name: &str, content: &str) -> String {
+        match load(name, "json", content).and_then(|desc| desc.build(&args())) {
+            Ok(_) => panic!("{} is accepted", name),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    /**
+     * A sphere of `material`, which is one of `materials`, lit by `lights`
+     */
+    fn scene(materials: &str, material: &str, lights: &str) -> String {
+        format!(
+            r#"{{"camera": {{"origin": [0, 10, 30], "dir": [0, 0, -1]}}, "materials": {{{}}},
+                "objects": [{{"geometry": {{"type": "sphere", "center": [0, 0, 0], "radius": 5}}, "material": "{}"}}],
+                "lights": [{}]}}"#,
+            materials, material, lights
+        )
+    }
+
+    const POINT: &str = r#"{"type": "point", "at": [0, 20, 0], "color": [1, 1, 1], "total_flux": 1000}"#;
+    const WHITE: &str = r#""white": {"type": "general", "diffusion_ratio": 1}"#;
+
+    #[test]
+    fn bundled_scenes_build() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let scene = SceneDesc::load(&path).and_then(|desc| desc.build(&args()));
+            assert!(scene.is_ok(), "{}: {}", path.display(), scene.err().unwrap());
+        }
+    }
+
+    #[test]
+    fn toml_matches_json() {
+        let json = load("json", "json", &scene(WHITE, "white", POINT)).unwrap();
+        let toml = load("toml", "toml", r#"
+            [camera]
+            origin = [0, 10, 30]
+            dir = [0, 0, -1]
+
+            [materials.white]
+            type = "general"
+            diffusion_ratio = 1
+
+            [[objects]]
+            material = "white"
+            geometry = { type = "sphere", center = [0, 0, 0], radius = 5 }
+
+            [[lights]]
+            type = "point"
+            at = [0, 20, 0]
+            color = [1, 1, 1]
+            total_flux = 1000
+        "#).unwrap();
+        assert_eq!(format!("{:?}", toml), format!("{:?}", json));
+    }
+
+    #[test]
+    fn reports_the_path_of_unknown_fields() {
+        let content = r#"{"camera": {"origin": [0, 0, 0], "dir": [0, 0, -1], "fov": 40}}"#;
+        match load("unknown", "json", content) {
+            Err(SceneFileError::Parse { path, msg }) => {
+                assert_eq!(path, "camera.fov");
+                assert!(msg.contains("unknown field `fov`"), "{}", msg);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_iors() {
+        let glass = |n2: &str| format!(r#""glass": {{"type": "dielectric", "n2": {}}}"#, n2);
+        for n2 in &["1.5", "2", r#"{"type": "cauchy", "a": 1.5, "b": 0.004}"#,
+                    r#"{"type": "sellmeier", "b": [1.04, 0.23, 1.01], "c": [0.006, 0.02, 103.6]}"#] {
+            let built = load("ior", "json", &scene(&glass(n2), "glass", POINT)).and_then(|desc| desc.build(&args()));
+            assert!(built.is_ok(), "{}: {}", n2, built.err().unwrap());
+        }
+    }
+
+    #[test]
+    fn reports_invalid_iors_from_the_formula() {
+        let content = scene(r#""glass": {"type": "dielectric", "n2": {"type": "sellmeier", "b": [1, 2], "c": [1, 2, 3]}}"#, "glass", POINT);
+        let msg = build_error("sellmeier", &content);
+        assert!(msg.contains("invalid length 2"), "{}", msg);
+
+        let content = scene(r#""glass": {"type": "dielectric", "n2": {"type": "abbe", "a": 1}}"#, "glass", POINT);
+        let msg = build_error("abbe", &content);
+        assert!(msg.contains("unknown variant `abbe`"), "{}", msg);
+
+        let content = scene(r#""glass": {"type": "dielectric", "n2": "glass"}"#, "glass", POINT);
+        let msg = build_error("string", &content);
+        assert!(msg.contains("a number, or a table with `type` cauchy or sellmeier"), "{}", msg);
+    }
+
+    #[test]
+    fn reports_invalid_light_colors_from_the_spectrum() {
+        let light = r#"{"type": "point", "at": [0, 20, 0], "color": {"type": "blackbody"}, "total_flux": 1000}"#;
+        let msg = build_error("blackbody", &scene(WHITE, "white", light));
+        assert!(msg.starts_with("lights[0]"), "{}", msg);
+        assert!(msg.contains("missing field `temperature`"), "{}", msg);
+
+        let light = r#"{"type": "point", "at": [0, 20, 0], "color": [1, 1], "total_flux": 1000}"#;
+        let msg = build_error("rgb", &scene(WHITE, "white", light));
+        assert!(msg.contains("invalid length 2"), "{}", msg);
+    }
+
+    #[test]
+    fn reports_the_path_of_invalid_values() {
+        let content = scene(WHITE, "white", POINT).replace(r#""radius": 5"#, r#""radius": -1"#);
+        assert_eq!(build_error("radius", &content), "objects[0].geometry.radius: must be a positive number, got -1");
+
+        let content = scene(WHITE, "black", POINT);
+        assert_eq!(build_error("material", &content), "objects[0].material: unknown material `black`");
+    }
+}
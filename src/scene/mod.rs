@@ -1,5 +1,10 @@
+pub mod file;
+pub mod obj;
+
 use super::camera::Camera;
+use super::environment::Environment;
 use super::light::*;
+use super::medium::Media;
 use super::object::geometry::GeometryGroup;
 use super::object::geometry::GeometryObject;
 use super::object::Object;
@@ -8,14 +13,39 @@ use super::renderer::*;
 use super::Args;
 use nalgebra::Vector3;
 use std::convert::Into;
+use std::path::Path;
+use std::sync::Arc;
 
 pub struct Scene {
     pub objs: ObjectGroup<Box<dyn Object>>,
-    pub lights: Vec<Box<dyn Light>>,
+    pub lights: LightSet,
     pub camera: Camera,
+    /// Seen by rays leaving the scene, also registered as a light if present
+    pub environment: Option<Arc<Environment>>,
+    /// Bounded media, in addition to the global fog
+    pub media: Media,
+}
+
+pub type SceneBuilder = fn(&Args) -> Scene;
+
+/**
+ * Built-in scenes, as (name, description, builder)
+ */
+pub const BUILTIN_SCENES: &[(&str, &str, SceneBuilder)] = &[
+    ("box", "Glass box and spheres in a room, see report/final/refraction", Scene::box_scene),
+    ("focus", "Three spheres for depth of field, see report/final/depth", Scene::focus_scene),
+    ("volumetric", "Beam light through fog above water, see report/final/volumetric", Scene::volumetric_scene),
+];
+
+pub fn builtin(name: &str) -> Option<SceneBuilder> {
+    BUILTIN_SCENES.iter().find(|(n, _, _)| *n == name).map(|(_, _, builder)| *builder)
 }
 
 impl Scene {
+    pub fn from_file(path: &Path, args: &Args) -> Result<Scene, file::SceneFileError> {
+        file::SceneDesc::load(path)?.build(args)
+    }
+
     pub fn box_scene(args: &Args) -> Scene {
         let light = SemisphereLight::new(
             Point::new(10f64, 60f64, 20f64),
@@ -176,8 +206,10 @@ impl Scene {
 
         Scene {
             objs: objs.into(),
-            lights: vec![Box::new(light)],
+            lights: LightSet::new(vec![Box::new(light)]),
             camera,
+            environment: None,
+            media: Media::default(),
         }
     }
 
@@ -275,8 +307,10 @@ impl Scene {
 
         Scene {
             objs: objs.into(),
-            lights: vec![Box::new(light)],
+            lights: LightSet::new(vec![Box::new(light)]),
             camera,
+            environment: None,
+            media: Media::default(),
         }
     }
 
@@ -321,23 +355,27 @@ impl Scene {
         )
         .into();
 
+        // Tinted blue-green by the depth traveled
         let water_mat = super::material::general::General::new(
             0f64,
-            0.6f64,
             0f64,
+            1f64,
             Vector3::new(1f64, 1f64, 1f64),
             Vector3::new(1f64, 1f64, 1f64),
             1f64,
             1.333f64,
             0f64,
-        );
+        )
+        .with_absorption(Color::new(0.08f64, 0.02f64, 0.01f64));
 
+        #[allow(unused_variables)]
         let base_1_geo: GeometryGroup<_> = super::object::geometry::util::create_box(
             Vector3::new(0f64, 10f64, 225f64),
             Vector3::new(50f64, 40f64, 275f64),
         )
         .into();
 
+        #[allow(unused_variables)]
         let base_1_mat = super::material::general::General::new(
             1f64,
             0f64,
@@ -349,12 +387,14 @@ impl Scene {
             0f64,
         );
 
+        #[allow(unused_variables)]
         let glass_1_geo: GeometryGroup<_> = super::object::geometry::util::create_box(
             Vector3::new(0f64, 40f64, 225f64),
             Vector3::new(50f64, 120f64, 275f64),
         )
         .into();
 
+        #[allow(unused_variables)]
         let glass_mat = super::material::general::General::new(
             0f64,
             0f64,
@@ -433,11 +473,27 @@ impl Scene {
         Scene {
             objs: objs.into(),
             camera,
-            lights,
+            lights: LightSet::new(lights),
+            environment: None,
+            media: Media::default(),
         }
     }
 
-    pub fn intersect(&self, ray: &Ray) -> Option<super::object::Intersect> {
+    pub fn intersect(&self, ray: &Ray) -> Option<super::object::Intersect<'_>> {
         self.objs.intersect(ray, None)
     }
+
+    /**
+     * Color seen by rays leaving the scene along `dir`
+     */
+    pub fn background(&self, dir: &Dir) -> Color {
+        self.environment.as_ref().map(|env| env.lookup(dir)).unwrap_or_else(Color::zeros)
+    }
+
+    /**
+     * Whether anything blocks the segment from `from` along `dir` within `dist`
+     */
+    pub fn occluded(&self, from: &Point, dir: &Dir, dist: f64) -> bool {
+        self.objs.intersect(&Ray::new(*from, *dir), Some(dist - crate::consts::EPS)).is_some()
+    }
 }
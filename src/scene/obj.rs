@@ -0,0 +1,228 @@
+use crate::consts::*;
+use crate::light::{AreaLight, Light};
+use crate::material::emissive::Emissive;
+use crate::material::general::General;
+use crate::material::Material;
+use crate::object::geometry::mesh::Mesh;
+use crate::object::geometry::GeometryObject;
+use crate::object::Object;
+use crate::renderer::*;
+use crate::texture::{ImageTexture, NormalMap, Texture};
+use log::{info, warn};
+use std::path::Path;
+use std::sync::Arc;
+
+fn to_color(c: [f64; 3]) -> Color {
+    Color::new(c[0], c[1], c[2])
+}
+
+/**
+ * Parses a color from parameters unknown to tobj, either a single value or RGB
+ */
+fn param_color(mtl: &tobj::Material, key: &str) -> Option<Color> {
+    let parsed = mtl.unknown_param.get(key)?
+        .split_whitespace()
+        .map(|c| c.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    match parsed.as_slice() {
+        [c] => Some(Color::new(*c, *c, *c)),
+        [r, g, b] => Some(Color::new(*r, *g, *b)),
+        _ => None,
+    }
+}
+
+/**
+ * Loads a texture of a MTL material, only the `-bm` option is supported
+ */
+fn load_texture(mtl: &tobj::Material, spec: &str, base: &Path) -> Option<(Arc<dyn Texture>, Option<f64>)> {
+    let tokens: Vec<&str> = spec.split_whitespace().collect();
+    let file = base.join(tokens.last()?);
+    let bump_multiplier = tokens.iter()
+        .position(|t| *t == "-bm")
+        .and_then(|i| tokens.get(i + 1))
+        .and_then(|bm| bm.parse::<f64>().ok());
+
+    match ImageTexture::load(&file) {
+        Ok(texture) => Some((Arc::new(texture), bump_multiplier)),
+        Err(e) => {
+            warn!("Failed to load texture {} of material {}: {}", file.display(), mtl.name, e);
+            None
+        }
+    }
+}
+
+/**
+ * Maps MTL parameters onto the general material.
+ *
+ * - Kd / Ks decide the diffusion & pure reflection ratio of the opaque portion, and their normalized colors
+ * - d (dissolve) is the opaque portion, the rest is refracted
+ * - Ni is the refractive index, assuming the outside is vacuum
+ * - Ns is converted to the stddev of glossy reflection, or mirror-like if absent
+ * - map_Kd is multiplied onto the diffusion throughput, relative to `base`
+ * - norm is a tangent space normal map, or map_Bump / bump a height map if absent
+ *
+ * Emissive materials (Ke) are handled in `load` instead.
+ */
+pub fn map_material(mtl: &tobj::Material, base: &Path) -> General {
+    let kd = to_color(mtl.diffuse);
+    let ks = to_color(mtl.specular);
+    let opacity = mtl.dissolve.clamp(0f64, 1f64);
+
+    let kd_max = kd.max().max(0f64);
+    let ks_max = ks.max().max(0f64);
+
+    // Keep the opaque part energy conserving
+    let scale = if kd_max + ks_max > 1f64 { 1f64 / (kd_max + ks_max) } else { 1f64 };
+    let diffusion_ratio = opacity * kd_max * scale;
+    let pure_reflection_ratio = opacity * ks_max * scale;
+    let refraction_ratio = 1f64 - opacity;
+
+    let diffusion_throughput = if kd_max > EPS { kd / kd_max } else { Color::new(1f64, 1f64, 1f64) };
+
+    // The general material uses a single throughput for both reflection and refraction
+    let refraction_throughput = if refraction_ratio > EPS {
+        param_color(mtl, "Tf").unwrap_or_else(|| Color::new(1f64, 1f64, 1f64))
+    } else if ks_max > EPS {
+        ks / ks_max
+    } else {
+        Color::new(1f64, 1f64, 1f64)
+    };
+
+    let n2 = if mtl.optical_density > EPS { mtl.optical_density } else { 1f64 };
+
+    let glossy_stddev = if mtl.shininess > EPS && mtl.shininess < 1000f64 {
+        (2f64 / (mtl.shininess + 2f64)).sqrt()
+    } else {
+        0f64
+    };
+
+    let mut material = General::new(
+        diffusion_ratio,
+        pure_reflection_ratio,
+        refraction_ratio,
+        refraction_throughput,
+        diffusion_throughput,
+        1f64,
+        n2,
+        glossy_stddev,
+    );
+
+    if let Some((texture, _)) = load_texture(mtl, &mtl.diffuse_texture, base) {
+        material = material.with_diffusion_texture(texture);
+    }
+
+    if let Some((texture, _)) = mtl.unknown_param.get("norm").and_then(|spec| load_texture(mtl, spec, base)) {
+        material = material.with_normal_map(NormalMap::Tangent(texture));
+    } else if let Some((height, bm)) = load_texture(mtl, &mtl.normal_texture, base) {
+        material = material.with_normal_map(NormalMap::Bump { height, scale: bm.unwrap_or(1f64) });
+    }
+
+    material
+}
+
+pub struct Model {
+    pub objs: Vec<Box<dyn Object>>,
+    /// Area lights over the emissive parts
+    pub lights: Vec<Box<dyn Light>>,
+}
+
+/**
+ * Maps a MTL material onto an emissive material if it has a positive Ke, or the general material otherwise
+ */
+fn map_any_material(mtl: &tobj::Material, base: &Path) -> Arc<dyn Material> {
+    match param_color(mtl, "Ke") {
+        Some(ke) if ke.max() > 0f64 => Arc::new(Emissive::new(ke)),
+        _ => Arc::new(map_material(mtl, base)),
+    }
+}
+
+/**
+ * Loads a Wavefront OBJ file, producing one object per model, and area lights for models with emissive materials.
+ *
+ * Vertices are transformed by `scale` then `translate`. If `material` is given, it overrides the MTL materials.
+ */
+pub fn load(
+    path: &Path,
+    scale: f64,
+    translate: Point,
+    material: Option<Arc<dyn Material>>,
+) -> Result<Model, tobj::LoadError> {
+    let (models, mtls) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+
+    let mtls: Vec<Arc<dyn Material>> = match mtls {
+        Ok(mtls) => {
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+            mtls.iter().map(|mtl| map_any_material(mtl, base)).collect()
+        }
+        Err(e) => {
+            if material.is_none() {
+                warn!("Failed to load MTL for {}: {}, using the default material", path.display(), e);
+            }
+            Vec::new()
+        }
+    };
+
+    let default_material: Arc<dyn Material> = Arc::new(General::new(
+        1f64,
+        0f64,
+        0f64,
+        Color::new(1f64, 1f64, 1f64),
+        Color::new(0.8f64, 0.8f64, 0.8f64),
+        1f64,
+        1f64,
+        0f64,
+    ));
+
+    let mut objs: Vec<Box<dyn Object>> = Vec::with_capacity(models.len());
+    let mut lights: Vec<Box<dyn Light>> = Vec::new();
+    let mut total = 0;
+    let mut skipped = 0;
+
+    for model in models.iter() {
+        let mesh = &model.mesh;
+        let positions: Vec<Point> = mesh.positions.chunks_exact(3)
+            .map(|p| Point::new(p[0], p[1], p[2]) * scale + translate)
+            .collect();
+        // Flipped along with the winding, see below
+        let normals = if mesh.normals.is_empty() {
+            None
+        } else {
+            Some(mesh.normals.chunks_exact(3).map(|n| -Dir::new(n[0], n[1], n[2])).collect())
+        };
+        let uvs = if mesh.texcoords.is_empty() {
+            None
+        } else {
+            Some(mesh.texcoords.chunks_exact(2).map(|t| UV::new(t[0], t[1])).collect())
+        };
+
+        // OBJ faces are counter-clockwise from the outside, while our normals point inwards
+        let faces: Vec<[u32; 3]> = mesh.indices.chunks_exact(3).map(|face| [face[0], face[2], face[1]]).collect();
+        let face_count = faces.len();
+
+        let geo = Arc::new(Mesh::new(positions, faces, normals, uvs));
+        skipped += face_count - geo.len();
+        if geo.is_empty() {
+            continue;
+        }
+        total += geo.len();
+
+        let mat = material.clone().unwrap_or_else(|| {
+            mesh.material_id.and_then(|id| mtls.get(id).cloned()).unwrap_or_else(|| default_material.clone())
+        });
+
+        let emission = mat.emission();
+        if emission.max() > 0f64 {
+            lights.push(Box::new(AreaLight::new(vec![Box::new(geo.clone())], emission)));
+        }
+
+        objs.push(Box::new(GeometryObject::new(geo, mat)));
+    }
+
+    info!(
+        "Loaded {}: {} models, {} triangles, {} degenerated triangles skipped, {} emissive models",
+        path.display(), objs.len(), total, skipped, lights.len()
+    );
+
+    Ok(Model { objs, lights })
+}
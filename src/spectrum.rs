@@ -0,0 +1,316 @@
+use crate::light::Light;
+use crate::renderer::*;
+use nalgebra::Matrix3;
+use rand::{Rng, RngCore};
+use std::sync::OnceLock;
+
+/// Visible range sampled in the spectral mode, in nm
+pub const LAMBDA_MIN: f64 = 380f64;
+pub const LAMBDA_MAX: f64 = 780f64;
+
+// Sodium D line, where IORs are usually quoted. Used for dispersive IORs outside the spectral mode
+const LAMBDA_D: f64 = 589.3f64;
+
+// Spacing of tabulated spectra and numerical integration, in nm
+const STEP: f64 = 1f64;
+
+fn luminance(c: &Color) -> f64 {
+    0.2126f64 * c[0] + 0.7152f64 * c[1] + 0.0722f64 * c[2]
+}
+
+fn lobe(lambda: f64, mu: f64, sigma_lower: f64, sigma_upper: f64) -> f64 {
+    let t = (lambda - mu) / if lambda < mu { sigma_lower } else { sigma_upper };
+    (-t * t / 2f64).exp()
+}
+
+/**
+ * CIE 1931 2-degree color matching functions, with the multi-lobe fit of Wyman, Sloan and Shirley,
+ * "Simple Analytic Approximations to the CIE XYZ Color Matching Functions", 2013
+ */
+fn cie_xyz(lambda: f64) -> Color {
+    Color::new(
+        1.056f64 * lobe(lambda, 599.8f64, 37.9f64, 31.0f64) + 0.362f64 * lobe(lambda, 442.0f64, 16.0f64, 26.7f64)
+            - 0.065f64 * lobe(lambda, 501.1f64, 20.4f64, 26.2f64),
+        0.821f64 * lobe(lambda, 568.8f64, 46.9f64, 40.5f64) + 0.286f64 * lobe(lambda, 530.9f64, 16.3f64, 31.1f64),
+        1.217f64 * lobe(lambda, 437.0f64, 11.8f64, 36.0f64) + 0.681f64 * lobe(lambda, 459.0f64, 26.0f64, 13.8f64),
+    )
+}
+
+fn xyz_to_srgb() -> Matrix3<f64> {
+    Matrix3::new(
+        3.240_454_2f64, -1.537_138_5f64, -0.498_531_4f64,
+        -0.969_266_0f64, 1.876_010_8f64, 0.041_556_0f64,
+        0.055_643_4f64, -0.204_025_9f64, 1.057_225_2f64,
+    )
+}
+
+fn smoothstep(lower: f64, upper: f64, x: f64) -> f64 {
+    let t = ((x - lower) / (upper - lower)).clamp(0f64, 1f64);
+    t * t * (3f64 - 2f64 * t)
+}
+
+/**
+ * Red, green and blue spectra for upsampling RGB, summing up to 1 at every wavelength
+ */
+fn basis(lambda: f64) -> Color {
+    let red = smoothstep(560f64, 610f64, lambda);
+    let blue = 1f64 - smoothstep(470f64, 520f64, lambda);
+    Color::new(red, 1f64 - red - blue, blue)
+}
+
+/**
+ * Midpoint rule over the visible range
+ */
+fn integrate<T: std::ops::Add<Output = T> + std::ops::Mul<f64, Output = T>>(zero: T, f: impl Fn(f64) -> T) -> T {
+    let steps = ((LAMBDA_MAX - LAMBDA_MIN) / STEP) as usize;
+    (0..steps).fold(zero, |acc, i| acc + f(LAMBDA_MIN + (i as f64 + 0.5f64) * STEP) * STEP)
+}
+
+struct Tables {
+    // Integral of the luminance matching function
+    y_integral: f64,
+    // From RGB to the weights of the basis, such that the emission looks like the RGB color
+    emission: Matrix3<f64>,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let y_integral = integrate(0f64, |l| cie_xyz(l)[1]);
+        let response = integrate(Matrix3::zeros(), |l| (xyz_to_srgb() * cie_xyz(l) / y_integral) * basis(l).transpose());
+        Tables {
+            y_integral,
+            emission: response.try_inverse().expect("Singular RGB basis"),
+        }
+    })
+}
+
+/**
+ * Linear sRGB of unit spectral radiance at `lambda`, scaled such that a flat spectrum of 1 has a luminance of 1
+ */
+fn rgb_response(lambda: f64) -> Color {
+    xyz_to_srgb() * cie_xyz(lambda) / tables().y_integral
+}
+
+/**
+ * What paths carry: RGB colors, or the value at a single wavelength in the spectral mode
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wavelength {
+    Rgb,
+    /// In nm. Values are repeated in all channels of colors
+    Sampled(f64),
+}
+
+impl Wavelength {
+    /**
+     * Uniform over the visible range if `spectral`. Nothing is drawn from `rng` otherwise
+     */
+    pub fn sample(spectral: bool, rng: &mut dyn RngCore) -> Self {
+        if spectral {
+            Wavelength::Sampled(LAMBDA_MIN + rng.gen::<f64>() * (LAMBDA_MAX - LAMBDA_MIN))
+        } else {
+            Wavelength::Rgb
+        }
+    }
+
+    pub fn nm(self) -> Option<f64> {
+        match self {
+            Wavelength::Rgb => None,
+            Wavelength::Sampled(lambda) => Some(lambda),
+        }
+    }
+
+    /**
+     * Reflectances, throughputs and coefficients given in RGB. White stays 1 at all wavelengths
+     */
+    pub fn filter(self, color: &Color) -> Color {
+        match self {
+            Wavelength::Rgb => *color,
+            Wavelength::Sampled(lambda) => Color::repeat(basis(lambda).dot(color)),
+        }
+    }
+
+    /**
+     * Radiance, irradiance and flux given in RGB, upsampled such that they look the same after `to_rgb`
+     */
+    pub fn radiance(self, color: &Color) -> Color {
+        match self {
+            Wavelength::Rgb => *color,
+            Wavelength::Sampled(lambda) => Color::repeat((tables().emission * color).dot(&basis(lambda)).max(0f64)),
+        }
+    }
+
+    /**
+     * Like `radiance`, following the spectrum of `light` if it has one
+     */
+    pub fn light(self, light: &dyn Light, color: &Color) -> Color {
+        match (self, light.spectrum()) {
+            (Wavelength::Sampled(lambda), Some(spectrum)) => Color::repeat(luminance(color) * spectrum.eval(lambda)),
+            _ => self.radiance(color),
+        }
+    }
+
+    /**
+     * Estimate in RGB of what the path carries, for accumulating into images and photon maps
+     */
+    pub fn to_rgb(self, value: &Color) -> Color {
+        match self {
+            Wavelength::Rgb => *value,
+            Wavelength::Sampled(lambda) => rgb_response(lambda) * (value.mean() * (LAMBDA_MAX - LAMBDA_MIN)),
+        }
+    }
+}
+
+/**
+ * Spectral power distribution over the visible range, normalized to a luminance of 1
+ */
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    // Every `STEP` nm from `LAMBDA_MIN`
+    values: Vec<f64>,
+}
+
+impl Spectrum {
+    /**
+     * None if nothing is visible
+     */
+    fn tabulate(f: impl Fn(f64) -> f64) -> Option<Self> {
+        let count = ((LAMBDA_MAX - LAMBDA_MIN) / STEP) as usize + 1;
+        let values: Vec<f64> = (0..count).map(|i| f(LAMBDA_MIN + i as f64 * STEP).max(0f64)).collect();
+
+        let spectrum = Spectrum { values };
+        let y = integrate(0f64, |l| spectrum.eval(l) * cie_xyz(l)[1]) / tables().y_integral;
+        if !y.is_finite() || y <= 0f64 {
+            return None;
+        }
+
+        Some(Spectrum { values: spectrum.values.iter().map(|v| v / y).collect() })
+    }
+
+    /**
+     * Ideal black body at `temperature` in Kelvin
+     */
+    pub fn blackbody(temperature: f64) -> Option<Self> {
+        // Second radiation constant, in nm K
+        const C2: f64 = 1.438_777e7f64;
+        Self::tabulate(|lambda| lambda.powi(-5) / ((C2 / (lambda * temperature)).exp() - 1f64))
+    }
+
+    /**
+     * Linearly interpolated between (wavelength in nm, value) pairs sorted by wavelength, zero outside
+     */
+    pub fn sampled(points: &[(f64, f64)]) -> Option<Self> {
+        Self::tabulate(|lambda| {
+            let idx = points.partition_point(|p| p.0 <= lambda);
+            if idx == 0 || idx == points.len() {
+                return if idx > 0 && points[idx - 1].0 == lambda { points[idx - 1].1 } else { 0f64 };
+            }
+            let ((l0, v0), (l1, v1)) = (points[idx - 1], points[idx]);
+            v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+        })
+    }
+
+    pub fn eval(&self, lambda: f64) -> f64 {
+        let x = (lambda - LAMBDA_MIN) / STEP;
+        if x < 0f64 || x > (self.values.len() - 1) as f64 {
+            return 0f64;
+        }
+        let idx = (x.floor() as usize).min(self.values.len() - 2);
+        let t = x - idx as f64;
+        self.values[idx] * (1f64 - t) + self.values[idx + 1] * t
+    }
+
+    /**
+     * Linear sRGB color of the spectrum, with a luminance of 1
+     */
+    pub fn rgb(&self) -> Color {
+        integrate(Color::zeros(), |l| rgb_response(l) * self.eval(l))
+    }
+}
+
+/**
+ * Index of refraction, possibly varying with the wavelength
+ */
+#[derive(Clone, Debug)]
+pub enum Ior {
+    Constant(f64),
+    /// n = a + b / λ^2 + c / λ^4, λ in μm
+    Cauchy { a: f64, b: f64, c: f64 },
+    /// n^2 = 1 + Σ b_i λ^2 / (λ^2 - c_i), λ in μm
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    /**
+     * Outside the spectral mode, at the sodium D line
+     */
+    pub fn at(&self, wavelength: Wavelength) -> f64 {
+        let um2 = (wavelength.nm().unwrap_or(LAMBDA_D) / 1000f64).powi(2);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b, c } => a + b / um2 + c / (um2 * um2),
+            Ior::Sellmeier { b, c } => (1f64 + (0..3).map(|i| b[i] * um2 / (um2 - c[i])).sum::<f64>()).max(0f64).sqrt(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Expected RGB over uniformly sampled wavelengths
+     */
+    fn average(f: impl Fn(Wavelength) -> Color) -> Color {
+        integrate(Color::zeros(), |l| f(Wavelength::Sampled(l))) / (LAMBDA_MAX - LAMBDA_MIN)
+    }
+
+    #[test]
+    fn radiance_round_trips_through_rgb() {
+        for color in &[Color::new(1f64, 1f64, 1f64), Color::new(0.8f64, 0.5f64, 0.2f64), Color::new(0.1f64, 0.3f64, 0.6f64)] {
+            let rgb = average(|w| w.to_rgb(&w.radiance(color)));
+            assert!((rgb - color).norm() < 1e-9f64, "{:?} became {:?}", color, rgb);
+        }
+    }
+
+    #[test]
+    fn white_filters_nothing() {
+        let white = Color::new(1f64, 1f64, 1f64);
+        for i in 0..=40 {
+            let w = Wavelength::Sampled(LAMBDA_MIN + i as f64 * 10f64);
+            assert!((w.filter(&white) - white).norm() < 1e-12f64);
+        }
+        assert_eq!(Wavelength::Rgb.filter(&Color::new(0.1f64, 0.2f64, 0.3f64)), Color::new(0.1f64, 0.2f64, 0.3f64));
+    }
+
+    #[test]
+    fn spectra_have_unit_luminance() {
+        let flat = Spectrum::sampled(&[(LAMBDA_MIN, 2f64), (LAMBDA_MAX, 2f64)]).unwrap();
+        let warm = Spectrum::blackbody(3000f64).unwrap().rgb();
+        let cold = Spectrum::blackbody(10000f64).unwrap().rgb();
+        for rgb in &[flat.rgb(), warm, cold] {
+            assert!((luminance(rgb) - 1f64).abs() < 1e-3f64, "{:?}", rgb);
+        }
+        assert!(warm[0] > warm[2] && cold[2] > cold[0]);
+    }
+
+    #[test]
+    fn invisible_spectra_are_rejected() {
+        assert!(Spectrum::sampled(&[(100f64, 1f64), (200f64, 1f64)]).is_none());
+        assert!(Spectrum::sampled(&[]).is_none());
+    }
+
+    #[test]
+    fn iors_follow_their_formula() {
+        let bk7 = Ior::Sellmeier {
+            b: [1.039_612_12f64, 0.231_792_344f64, 1.010_469_45f64],
+            c: [0.006_000_698_67f64, 0.020_017_914_4f64, 103.560_653f64],
+        };
+        assert!((bk7.at(Wavelength::Rgb) - 1.5168f64).abs() < 1e-4f64);
+        assert!(bk7.at(Wavelength::Sampled(400f64)) > bk7.at(Wavelength::Sampled(700f64)));
+
+        let cauchy = Ior::Cauchy { a: 1.5f64, b: 0.01f64, c: 0f64 };
+        assert!((cauchy.at(Wavelength::Sampled(500f64)) - 1.54f64).abs() < 1e-12f64);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::light::tangents;
+use crate::renderer::*;
+use std::path::Path;
+use std::sync::Arc;
+
+// Step in texture coordinates for the gradient of bump maps
+const BUMP_DELTA: f64 = 1e-3;
+
+/**
+ * Color varying over the surface parameterization
+ */
+pub trait Texture: Send + Sync {
+    fn lookup(&self, uv: &UV) -> Color;
+}
+
+pub struct ConstantTexture {
+    color: Color,
+}
+
+impl ConstantTexture {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for ConstantTexture {
+    fn lookup(&self, _uv: &UV) -> Color {
+        self.color
+    }
+}
+
+/**
+ * Alternating squares, `divisions` of them along both u and v within [0, 1]
+ */
+pub struct CheckerboardTexture {
+    even: Color,
+    odd: Color,
+    divisions: f64,
+}
+
+impl CheckerboardTexture {
+    pub fn new(even: Color, odd: Color, divisions: f64) -> Self {
+        Self { even, odd, divisions }
+    }
+}
+
+impl Texture for CheckerboardTexture {
+    fn lookup(&self, uv: &UV) -> Color {
+        let cell = (uv[0] * self.divisions).floor() + (uv[1] * self.divisions).floor();
+        if (cell as i64).rem_euclid(2) == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+fn srgb_decode(c: f64) -> f64 {
+    if c <= 0.040_45f64 {
+        c / 12.92f64
+    } else {
+        ((c + 0.055f64) / 1.055f64).powf(2.4f64)
+    }
+}
+
+/**
+ * Image repeated over the surface, with v = 0 at the bottom row as in OBJ files. Bilinearly filtered
+ */
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// Linear, rows from top to bottom
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    /**
+     * Loads a .hdr or .pfm image as is, or any other format supported by `image` as sRGB
+     */
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let is_linear = path.extension().map(|e| e == "hdr" || e == "pfm").unwrap_or(false);
+        let (width, height, pixels) = if is_linear {
+            crate::environment::read_linear(path)?
+        } else {
+            let img = image::open(path)?.to_rgb();
+            let decode = |c: u8| srgb_decode(c as f64 / 255f64);
+            let pixels = img.pixels().map(|p| Color::new(decode(p[0]), decode(p[1]), decode(p[2]))).collect();
+            (img.width() as usize, img.height() as usize, pixels)
+        };
+
+        if width == 0 || height == 0 {
+            return Err("empty image".into());
+        }
+
+        Ok(Self { width, height, pixels })
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn lookup(&self, uv: &UV) -> Color {
+        // Texel centers are at half integers
+        let x = uv[0] * self.width as f64 - 0.5f64;
+        let y = (1f64 - uv[1]) * self.height as f64 - 0.5f64;
+        if !x.is_finite() || !y.is_finite() {
+            return self.pixels[0];
+        }
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        self.texel(x0, y0) * ((1f64 - fx) * (1f64 - fy))
+            + self.texel(x0 + 1, y0) * (fx * (1f64 - fy))
+            + self.texel(x0, y0 + 1) * ((1f64 - fx) * fy)
+            + self.texel(x0 + 1, y0 + 1) * (fx * fy)
+    }
+}
+
+/**
+ * Perturbation of shading normals over the surface
+ */
+#[derive(Clone)]
+pub enum NormalMap {
+    /// Tangent space normals encoded as colors, with +X along increasing u and +Z outwards
+    Tangent(Arc<dyn Texture>),
+    /// Displacement outwards by the average of the channels, times `scale`
+    Bump { height: Arc<dyn Texture>, scale: f64 },
+}
+
+impl NormalMap {
+    /**
+     * `norm` points inwards as everywhere else, and `dpdu`, `dpdv` are the derivatives of the position over `uv`
+     */
+    pub fn perturb(&self, norm: &Dir, dpdu: &Dir, dpdv: &Dir, uv: &UV) -> Dir {
+        let outwards = -norm;
+
+        let perturbed = match self {
+            NormalMap::Tangent(texture) => {
+                let encoded = texture.lookup(uv) * 2f64 - Color::new(1f64, 1f64, 1f64);
+
+                let projected = dpdu - outwards * outwards.dot(dpdu);
+                let tangent = if projected.norm() < crate::consts::EPS {
+                    tangents(&outwards).0
+                } else {
+                    projected.normalize()
+                };
+                let mut bitangent = outwards.cross(&tangent);
+                if bitangent.dot(dpdv) < 0f64 {
+                    bitangent = -bitangent;
+                }
+
+                tangent * encoded[0] + bitangent * encoded[1] + outwards * encoded[2]
+            }
+            NormalMap::Bump { height, scale } => {
+                let sample = |du: f64, dv: f64| height.lookup(&(uv + UV::new(du, dv))).sum() / 3f64 * scale;
+                let dhdu = (sample(BUMP_DELTA, 0f64) - sample(-BUMP_DELTA, 0f64)) / (2f64 * BUMP_DELTA);
+                let dhdv = (sample(0f64, BUMP_DELTA) - sample(0f64, -BUMP_DELTA)) / (2f64 * BUMP_DELTA);
+
+                // Cross product of the displaced derivatives, with the shading normal in place of the geometric one
+                let cross = dpdu.cross(dpdv);
+                let sign = if cross.dot(&outwards) < 0f64 { -1f64 } else { 1f64 };
+                outwards * cross.norm() + (outwards.cross(dpdv) * dhdu + dpdu.cross(&outwards) * dhdv) * sign
+            }
+        };
+
+        if perturbed.norm() < crate::consts::EPS || !perturbed.iter().all(|c| c.is_finite()) {
+            return *norm;
+        }
+        -perturbed.normalize()
+    }
+}
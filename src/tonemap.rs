@@ -0,0 +1,155 @@
+use crate::renderer::*;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    /// Clamps each channel to [0, 1]
+    Clamp,
+    /// L / (1 + L) on luminance
+    Reinhard,
+    /// L (1 + L / Lw^2) / (1 + L) on luminance, where Lw is the white point
+    ReinhardExtended,
+    /// Narkowicz's fit of the ACES filmic curve, on each channel
+    Aces,
+}
+
+impl FromStr for Operator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(Operator::Clamp),
+            "reinhard" => Ok(Operator::Reinhard),
+            "reinhard-extended" => Ok(Operator::ReinhardExtended),
+            "aces" => Ok(Operator::Aces),
+            _ => Err(format!(
+                "unknown tone mapping operator `{}`, expected one of clamp, reinhard, reinhard-extended, aces",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapping {
+    operator: Operator,
+    /// Linear scale derived from the exposure in EV
+    scale: f64,
+    white_point: f64,
+}
+
+fn luminance(c: &Color) -> f64 {
+    0.2126f64 * c[0] + 0.7152f64 * c[1] + 0.0722f64 * c[2]
+}
+
+fn srgb_encode(c: f64) -> f64 {
+    if c <= 0.003_130_8f64 {
+        12.92f64 * c
+    } else {
+        1.055f64 * c.powf(1f64 / 2.4f64) - 0.055f64
+    }
+}
+
+impl ToneMapping {
+    pub fn new(operator: Operator, exposure: f64, white_point: f64) -> Self {
+        Self {
+            operator,
+            scale: 2f64.powf(exposure),
+            white_point,
+        }
+    }
+
+    /**
+     * Maps linear radiance to display-referred linear values in [0, 1]
+     */
+    pub fn map(&self, radiance: &Color) -> Color {
+        let c = radiance.map(|c| c.max(0f64)) * self.scale;
+
+        let mapped = match self.operator {
+            Operator::Clamp => c,
+            Operator::Reinhard | Operator::ReinhardExtended => {
+                let l = luminance(&c);
+                if l <= 0f64 {
+                    return Color::new(0f64, 0f64, 0f64);
+                }
+
+                let ld = if self.operator == Operator::Reinhard {
+                    l / (1f64 + l)
+                } else {
+                    l * (1f64 + l / (self.white_point * self.white_point)) / (1f64 + l)
+                };
+                c * (ld / l)
+            }
+            Operator::Aces => c.map(|x| (x * (2.51f64 * x + 0.03f64)) / (x * (2.43f64 * x + 0.59f64) + 0.14f64)),
+        };
+
+        mapped.map(|c| c.clamp(0f64, 1f64))
+    }
+
+    /**
+     * Maps linear radiance to 8-bit sRGB
+     */
+    pub fn encode_srgb8(&self, radiance: &Color) -> [u8; 3] {
+        let mapped = self.map(radiance);
+        let encode = |c: f64| (srgb_encode(c) * 255f64).round() as u8;
+        [encode(mapped[0]), encode(mapped[1]), encode(mapped[2])]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(c: f64) -> Color {
+        Color::new(c, c, c)
+    }
+
+    #[test]
+    fn parses_operators() {
+        assert_eq!("reinhard-extended".parse::<Operator>(), Ok(Operator::ReinhardExtended));
+        assert!("filmic".parse::<Operator>().unwrap_err().contains("`filmic`"));
+    }
+
+    #[test]
+    fn clamp_scales_by_the_exposure() {
+        let tone = ToneMapping::new(Operator::Clamp, 1f64, 1f64);
+        assert_eq!(tone.map(&Color::new(0.1f64, 0.3f64, 0.7f64)), Color::new(0.2f64, 0.6f64, 1f64));
+        assert_eq!(tone.map(&Color::new(-1f64, 0f64, 0.25f64)), Color::new(0f64, 0f64, 0.5f64));
+    }
+
+    #[test]
+    fn reinhard_keeps_the_hue() {
+        let tone = ToneMapping::new(Operator::Reinhard, 0f64, 1f64);
+        assert!((tone.map(&grey(1f64)) - grey(0.5f64)).norm() < 1e-12f64);
+
+        let color = Color::new(0.4f64, 0.2f64, 0.1f64);
+        let mapped = tone.map(&color);
+        assert!((mapped / mapped[0] - color / color[0]).norm() < 1e-12f64);
+        assert!(luminance(&mapped) < luminance(&color));
+        assert_eq!(tone.map(&grey(0f64)), grey(0f64));
+    }
+
+    #[test]
+    fn reinhard_extended_maps_the_white_point_to_white() {
+        let tone = ToneMapping::new(Operator::ReinhardExtended, 0f64, 4f64);
+        assert!((tone.map(&grey(4f64)) - grey(1f64)).norm() < 1e-12f64);
+        assert!(tone.map(&grey(2f64))[0] < 1f64);
+    }
+
+    #[test]
+    fn operators_are_monotonic() {
+        for &operator in &[Operator::Clamp, Operator::Reinhard, Operator::ReinhardExtended, Operator::Aces] {
+            let tone = ToneMapping::new(operator, 0f64, 8f64);
+            let mapped: Vec<f64> = (0..200).map(|i| tone.map(&grey(i as f64 * 0.05f64))[0]).collect();
+            assert_eq!(mapped[0], 0f64, "{:?}", operator);
+            assert!(mapped.windows(2).all(|w| w[0] <= w[1] && w[1] <= 1f64), "{:?}", operator);
+        }
+    }
+
+    #[test]
+    fn encodes_srgb8() {
+        let tone = ToneMapping::new(Operator::Clamp, 0f64, 1f64);
+        assert_eq!(tone.encode_srgb8(&Color::new(0f64, 0.5f64, 2f64)), [0, 188, 255]);
+        assert_eq!(tone.encode_srgb8(&grey(0.001f64)), [3, 3, 3]);
+    }
+}